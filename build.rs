@@ -0,0 +1,17 @@
+use sha2::{Digest, Sha256};
+
+/// Refuses to build with an extractor.dex other than the one extractor/build.sh recorded.
+fn main() {
+    println!("cargo:rerun-if-changed=src/extractor.dex");
+    println!("cargo:rerun-if-changed=src/extractor.dex.sha256");
+
+    let dex = std::fs::read("src/extractor.dex").expect("failed to read src/extractor.dex");
+    let recorded = std::fs::read_to_string("src/extractor.dex.sha256")
+        .expect("failed to read src/extractor.dex.sha256");
+    let recorded = recorded.trim();
+    let actual = format!("{:x}", Sha256::digest(&dex));
+    assert_eq!(
+        actual, recorded,
+        "src/extractor.dex does not match its recorded checksum, rebuild it with extractor/build.sh"
+    );
+}
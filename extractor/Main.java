@@ -0,0 +1,85 @@
+import android.app.ActivityThread;
+import android.content.Context;
+import android.content.pm.ApplicationInfo;
+import android.content.pm.PackageInfo;
+import android.content.pm.PackageManager;
+import android.graphics.Bitmap;
+import android.graphics.Canvas;
+import android.graphics.drawable.Drawable;
+import android.os.Looper;
+import android.util.Base64;
+
+import org.json.JSONException;
+import org.json.JSONObject;
+
+import java.io.ByteArrayOutputStream;
+
+/**
+ * Run by zilch on the device through app_process, with the rights of the shell user.
+ *
+ * Prints JSON lines, the first one being {"protocol": PROTOCOL}. Zilch refuses output of any
+ * other protocol version, so bump it whenever a record changes in a way older zilch would
+ * misread.
+ *
+ * Without arguments, prints {"id", "label", "uid", "version", "flags", "enabled"} for every
+ * installed app. With "icons size package...", prints {"id", "icon"} for each of the packages
+ * that has an icon, the icon being a base64 PNG of size by size pixels.
+ */
+public class Main {
+    public static final int PROTOCOL = 1;
+
+    public static void main(String[] args) throws JSONException {
+        Looper.prepareMainLooper();
+        Context context = ActivityThread.systemMain().getSystemContext();
+        PackageManager pm = context.getPackageManager();
+
+        System.out.println(new JSONObject().put("protocol", PROTOCOL));
+
+        if (args.length >= 2 && args[0].equals("icons")) {
+            int size = Integer.parseInt(args[1]);
+            for (int i = 2; i < args.length; i++) {
+                printIcon(pm, args[i], size);
+            }
+            return;
+        }
+
+        for (PackageInfo info : pm.getInstalledPackages(0)) {
+            printApp(pm, info);
+        }
+    }
+
+    @SuppressWarnings("deprecation")
+    private static void printApp(PackageManager pm, PackageInfo info) throws JSONException {
+        ApplicationInfo app = info.applicationInfo;
+        // JSONObject escapes line breaks, so odd labels still take up one line
+        System.out.println(new JSONObject()
+                .put("id", info.packageName)
+                .put("label", pm.getApplicationLabel(app).toString())
+                .put("uid", app.uid)
+                .put("version", info.versionCode)
+                .put("flags", app.flags)
+                .put("enabled", app.enabled));
+    }
+
+    private static void printIcon(PackageManager pm, String packageName, int size)
+            throws JSONException {
+        Drawable icon;
+        try {
+            icon = pm.getApplicationIcon(packageName);
+        } catch (PackageManager.NameNotFoundException e) {
+            return;
+        }
+
+        Bitmap bitmap = Bitmap.createBitmap(size, size, Bitmap.Config.ARGB_8888);
+        Canvas canvas = new Canvas(bitmap);
+        icon.setBounds(0, 0, size, size);
+        icon.draw(canvas);
+
+        ByteArrayOutputStream png = new ByteArrayOutputStream();
+        bitmap.compress(Bitmap.CompressFormat.PNG, 100, png);
+        bitmap.recycle();
+        System.out.println(new JSONObject()
+                .put("id", packageName)
+                .put("icon", Base64.encodeToString(png.toByteArray(), Base64.NO_WRAP)));
+    }
+}
@@ -0,0 +1,17 @@
+#!/bin/sh
+# Builds src/extractor.dex from Main.java and records its checksum, which the zilch build checks.
+# Needs ANDROID_JAR pointing at an android.jar and d8 on the PATH. `nix develop .#extractor`
+# provides both at the versions pinned in flake.lock, so the dex comes out the same every time.
+set -eu
+
+cd "$(dirname "$0")"
+out=$(mktemp -d)
+trap 'rm -rf "$out"' EXIT
+
+# the stubs only stand in for hidden classes at compile time, the device has the real ones
+javac --release 8 -implicit:none -classpath "$ANDROID_JAR" -sourcepath stubs -d "$out" Main.java
+d8 --release --min-api 24 --lib "$ANDROID_JAR" --output "$out" "$out/Main.class"
+
+cp "$out/classes.dex" ../src/extractor.dex
+sha256sum ../src/extractor.dex | cut -d ' ' -f 1 > ../src/extractor.dex.sha256
+cat ../src/extractor.dex.sha256
@@ -0,0 +1,12 @@
+package android.app;
+
+/** Compile-time stand-in for the hidden class, the device provides the real one. */
+public final class ActivityThread {
+    public static ActivityThread systemMain() {
+        throw new UnsupportedOperationException("stub");
+    }
+
+    public ContextImpl getSystemContext() {
+        throw new UnsupportedOperationException("stub");
+    }
+}
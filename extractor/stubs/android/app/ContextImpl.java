@@ -0,0 +1,7 @@
+package android.app;
+
+import android.content.Context;
+
+/** Compile-time stand-in for the hidden class, the device provides the real one. */
+public abstract class ContextImpl extends Context {
+}
@@ -9,7 +9,19 @@
     let
       forAllSystems =
         f:
-        nixpkgs.lib.genAttrs nixpkgs.lib.systems.flakeExposed (system: f nixpkgs.legacyPackages.${system});
+        nixpkgs.lib.genAttrs nixpkgs.lib.systems.flakeExposed (
+          system:
+          f (
+            import nixpkgs {
+              inherit system;
+              # the Android SDK is unfree and comes with a license to accept
+              config = {
+                allowUnfree = true;
+                android_sdk.accept_license = true;
+              };
+            }
+          )
+        );
     in
     {
 
@@ -27,6 +39,23 @@
           ];
         };
 
+        # builds src/extractor.dex with extractor/build.sh
+        extractor =
+          let
+            android = pkgs.androidenv.composeAndroidPackages {
+              platformVersions = [ "34" ];
+              buildToolsVersions = [ "34.0.0" ];
+            };
+            sdk = "${android.androidsdk}/libexec/android-sdk";
+          in
+          pkgs.mkShell {
+            buildInputs = [ pkgs.jdk17 ];
+            ANDROID_JAR = "${sdk}/platforms/android-34/android.jar";
+            shellHook = ''
+              export PATH="${sdk}/build-tools/34.0.0:$PATH"
+            '';
+          };
+
       });
     };
 }
@@ -1,84 +1,604 @@
-use crate::adb_shell_text::ShellCommandText;
+use crate::adb_shell_text::AndroidDevice;
+use crate::device::DeviceProps;
+use crate::listview::State;
+use crate::protected::Role;
+use crate::users::UserScope;
 use crate::{Package, PackageIdentifier, ShellRunError};
-use adb_client::ADBUSBDevice;
+use crate::{backup, host_store, journal};
+use std::fmt::Display;
 
+#[derive(Clone)]
 pub enum Action {
-    Uninstall(Package),
-    Revert(PackageIdentifier, crate::listview::State),
-    Disable(PackageIdentifier),
+    Uninstall(Package, UserScope),
+    Revert(PackageIdentifier, State, UserScope),
+    Disable(PackageIdentifier, UserScope),
+}
+
+/// Actions the frontend queued together.
+pub struct Queued {
+    pub actions: Vec<Action>,
+    /// Whether the user chose to go ahead with protected packages too.
+    pub allow_protected: bool,
+}
+
+/// An action the device refused, kept around so it can be retried.
+pub struct Failure {
+    pub action: Action,
+    pub error: ShellRunError,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Uninstall(pkg, users) => write!(f, "uninstall {} for {users}", pkg.id),
+            Action::Revert(id, _, users) => write!(f, "revert {id} for {users}"),
+            Action::Disable(id, users) => write!(f, "disable {id} for {users}"),
+        }
+    }
 }
 
 impl Action {
-    pub fn apply_on_device(
-        self,
-        device: &mut ADBUSBDevice,
-        device_version: u16,
-    ) -> Result<(), ShellRunError> {
+    pub fn package_id(&self) -> &PackageIdentifier {
         match self {
-            Action::Uninstall(pkg) => {
-                if pkg.path.is_empty() {
-                    return Err(ShellRunError::BackupNotPossible(pkg.id));
-                }
+            Action::Uninstall(pkg, _) => &pkg.id,
+            Action::Revert(id, _, _) | Action::Disable(id, _) => id,
+        }
+    }
 
-                let _copy_command_no_output = device.shell_command_text(&format!(
-                    "cp {} /data/local/tmp/{}.apk",
-                    pkg.path, pkg.id
-                ))?;
+    pub fn users(&self) -> UserScope {
+        match self {
+            Action::Uninstall(_, users)
+            | Action::Revert(_, _, users)
+            | Action::Disable(_, users) => *users,
+        }
+    }
 
-                let uninstall_command = if device_version < 20 {
-                    format!("pm block --user 0 {}", pkg.id)
-                } else {
-                    format!("pm uninstall --user 0 -k {}", pkg.id)
-                };
+    /// The state the package is in once this action succeeded.
+    pub fn resulting_state(&self) -> State {
+        match self {
+            Action::Uninstall(..) => State::Uninstalled,
+            Action::Revert(..) => State::Enabled,
+            Action::Disable(..) => State::Disabled,
+        }
+    }
 
-                let output = device.shell_command_text(&uninstall_command)?;
+    /// Why the action is refused, if it would take away a protected package without
+    /// the user allowing it.
+    pub fn protection(&self, props: &DeviceProps) -> Option<Role> {
+        match self {
+            Action::Revert(..) => None,
+            Action::Uninstall(..) | Action::Disable(..) if props.allow_protected => None,
+            Action::Uninstall(..) | Action::Disable(..) => {
+                props.protected.get(self.package_id()).copied()
+            }
+        }
+    }
 
-                if !output.contains("Success") {
-                    return Err(ShellRunError::UninstallFailed(pkg.id));
+    /// The shell commands `apply_on_device` runs for this action, without touching the device.
+    /// Steps that depend on what the device prints are described in `#` comments.
+    pub fn planned_commands(&self, props: &DeviceProps) -> Vec<String> {
+        let sdk_version = props.sdk_version;
+        let mut commands = vec![];
+        if let Some(role) = self.protection(props) {
+            commands.push(format!("# refused: {} is {role}", self.package_id()));
+            return commands;
+        }
+        match self {
+            Action::Uninstall(pkg, users) => {
+                let users = planned_users(props, *users, Listed::Installed, &mut commands);
+                let Some(&first_user) = users.first() else {
+                    return commands;
+                };
+                commands.extend(backup::planned_back_up(&pkg.id, first_user));
+                if props.pull_backups {
+                    commands.push(format!(
+                        "# pull {} to this computer",
+                        backup::backup_dir(&pkg.id)
+                    ));
+                }
+                for user in users {
+                    commands.push(uninstall_command(sdk_version, user, &pkg.id));
+                }
+            }
+            Action::Revert(id, State::Disabled, users) => {
+                for user in planned_users(props, *users, Listed::Disabled, &mut commands) {
+                    commands.push(enable_command(sdk_version, user, id));
                 }
             }
-            Action::Revert(id, crate::listview::State::Disabled) => {
-                let revert_command = if device_version < 20 {
-                    format!("pm unblock --user 0 {}", id)
-                } else {
-                    format!("pm enable {}", id)
+            Action::Revert(id, _uninstalled, users) => {
+                let Ok(users) = users_to_reinstall(props, *users, id) else {
+                    commands.push(format!(
+                        "# refused: no record of the users {id} was uninstalled for"
+                    ));
+                    return commands;
                 };
-
-                let output = device.shell_command_text(&revert_command)?;
-                if !output.contains("new state: enabled") {
-                    return Err(ShellRunError::RevertFailed(id));
+                for user in users {
+                    commands.push(reinstall_command(sdk_version, user, id));
+                    commands.push(format!(
+                        "# if the package is not found, install the APKs backed up in {}",
+                        backup::backup_dir(id)
+                    ));
+                }
+            }
+            Action::Disable(id, users) => {
+                for user in planned_users(props, *users, Listed::Enabled, &mut commands) {
+                    commands.push(disable_command(sdk_version, user, id));
                 }
             }
-            Action::Revert(id, _uninstalled) => {
-                let revert_command = if device_version < 20 {
-                    format!("pm unblock --user 0 {}", id)
-                } else {
-                    format!("pm install-existing {id}")
+        }
+        commands
+    }
+
+    /// Returns the users the action went through.
+    pub fn apply_on_device<D: AndroidDevice + ?Sized>(
+        self,
+        device: &mut D,
+        props: &DeviceProps,
+    ) -> Result<Vec<u32>, ShellRunError> {
+        if let Some(role) = self.protection(props) {
+            return Err(ShellRunError::Protected(self.package_id().clone(), role));
+        }
+        let sdk_version = props.sdk_version;
+        let acted_for = match self {
+            Action::Uninstall(pkg, users) => {
+                let users = users_to_act_on(device, props, users, &pkg.id, Listed::Installed)?;
+                // the backup comes from a user that still has the package
+                let Some(&first_user) = users.first() else {
+                    return Ok(users);
                 };
-                let output = device.shell_command_text(&revert_command)?;
+                backup::back_up(device, &pkg.id, first_user)?;
+                if props.pull_backups {
+                    host_store::pull(device, props, &pkg.id)?;
+                }
 
-                if !output.contains("inaccessible or not found") {
-                    return Ok(());
+                for &user in users.iter() {
+                    let output = device.shell_command_text(&uninstall_command(
+                        sdk_version,
+                        user,
+                        &pkg.id,
+                    ))?;
+
+                    if !output.contains("Success") {
+                        return Err(ShellRunError::UninstallFailed(pkg.id, output));
+                    }
+                }
+                users
+            }
+            Action::Revert(id, State::Disabled, users) => {
+                let users = users_to_act_on(device, props, users, &id, Listed::Disabled)?;
+                for &user in users.iter() {
+                    let output =
+                        device.shell_command_text(&enable_command(sdk_version, user, &id))?;
+                    if !output.contains("new state: enabled") {
+                        return Err(ShellRunError::RevertFailed(id, output));
+                    }
                 }
+                users
+            }
+            Action::Revert(id, _uninstalled, users) => {
+                let users = users_to_reinstall(props, users, &id)?;
+                for &user in users.iter() {
+                    let output =
+                        device.shell_command_text(&reinstall_command(sdk_version, user, &id))?;
 
-                let revert_command = format!("pm install -r --user 0 /data/local/tmp/{id}.apk");
-                let output = device.shell_command_text(&revert_command)?;
-                if !output.contains("Success") {
-                    return Err(ShellRunError::RevertFailed(id));
+                    if !output.contains("inaccessible or not found") {
+                        continue;
+                    }
+
+                    backup::restore(device, props, &id, user)?;
                 }
+                users
             }
-            Action::Disable(id) => {
-                let disable_command = if device_version < 20 {
-                    format!("pm block --user 0 {}", id)
-                } else {
-                    format!("pm disable-user {id}")
-                };
-                let output = device.shell_command_text(&disable_command)?;
-                if !output.contains("new state: disabled-user") {
-                    return Err(ShellRunError::DisableFailed(id));
+            Action::Disable(id, users) => {
+                let users = users_to_act_on(device, props, users, &id, Listed::Enabled)?;
+                for &user in users.iter() {
+                    let output =
+                        device.shell_command_text(&disable_command(sdk_version, user, &id))?;
+                    if !output.contains("new state: disabled-user") {
+                        return Err(ShellRunError::DisableFailed(id, output));
+                    }
                 }
+                users
+            }
+        };
+        Ok(acted_for)
+    }
+}
+
+/// Which packages `pm list packages` prints for a user.
+#[derive(Clone, Copy)]
+enum Listed {
+    Installed,
+    Enabled,
+    Disabled,
+}
+
+fn list_command(listed: Listed, user: u32) -> String {
+    match listed {
+        Listed::Installed => format!("pm list packages --user {user}"),
+        Listed::Enabled => format!("pm list packages -e --user {user}"),
+        Listed::Disabled => format!("pm list packages -d --user {user}"),
+    }
+}
+
+/// The users an action goes through. A single user is always tried, so a package missing
+/// there fails loudly, while all users only covers those listing the package, as an app is
+/// often installed for the owner but not for a work profile.
+fn users_to_act_on<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+    props: &DeviceProps,
+    users: UserScope,
+    id: &str,
+    listed: Listed,
+) -> Result<Vec<u32>, ShellRunError> {
+    if users != UserScope::All {
+        return Ok(users.ids(&props.users));
+    }
+    let mut listing = vec![];
+    for user in users.ids(&props.users) {
+        let raw_pkg_text = device.shell_command_text(&list_command(listed, user))?;
+        if raw_pkg_text
+            .lines()
+            .any(|line| line.trim().strip_prefix("package:") == Some(id))
+        {
+            listing.push(user);
+        }
+    }
+    Ok(listing)
+}
+
+/// The users a revert of an uninstall goes through. The device no longer lists the package
+/// for any of them, and `pm install-existing` would just as well hand it to users that never
+/// had it, so all users only covers those the journal has it uninstalled for.
+fn users_to_reinstall(
+    props: &DeviceProps,
+    users: UserScope,
+    id: &PackageIdentifier,
+) -> Result<Vec<u32>, ShellRunError> {
+    if users != UserScope::All {
+        return Ok(users.ids(&props.users));
+    }
+    let recorded = journal::uninstalled_for(&props.serial, id);
+    if recorded.is_empty() {
+        return Err(ShellRunError::RevertFailed(
+            id.clone(),
+            "zilch has no record of the users it was uninstalled for, revert it for one user"
+                .to_string(),
+        ));
+    }
+    Ok(users
+        .ids(&props.users)
+        .into_iter()
+        .filter(|user| recorded.contains(user))
+        .collect())
+}
+
+/// What `users_to_act_on` lists for dry runs, which cannot tell who has the package.
+fn planned_users(
+    props: &DeviceProps,
+    users: UserScope,
+    listed: Listed,
+    commands: &mut Vec<String>,
+) -> Vec<u32> {
+    let ids = users.ids(&props.users);
+    if users == UserScope::All {
+        commands.extend(ids.iter().map(|&user| list_command(listed, user)));
+    }
+    ids
+}
+
+// `pm block` and `pm unblock` predate the per-user uninstall and disable of API level 20
+
+fn uninstall_command(sdk_version: u16, user: u32, id: &str) -> String {
+    if sdk_version < 20 {
+        format!("pm block --user {user} {id}")
+    } else {
+        format!("pm uninstall --user {user} -k {id}")
+    }
+}
+
+fn enable_command(sdk_version: u16, user: u32, id: &str) -> String {
+    if sdk_version < 20 {
+        format!("pm unblock --user {user} {id}")
+    } else {
+        format!("pm enable --user {user} {id}")
+    }
+}
+
+fn reinstall_command(sdk_version: u16, user: u32, id: &str) -> String {
+    if sdk_version < 20 {
+        format!("pm unblock --user {user} {id}")
+    } else {
+        format!("pm install-existing --user {user} {id}")
+    }
+}
+
+fn disable_command(sdk_version: u16, user: u32, id: &str) -> String {
+    if sdk_version < 20 {
+        format!("pm block --user {user} {id}")
+    } else {
+        format!("pm disable-user --user {user} {id}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_device::{FakeDevice, Reply};
+    use crate::prepare_device;
+    use std::collections::BTreeSet;
+
+    const BLOAT: &str = "com.example.bloat";
+
+    fn bloat() -> Package {
+        Package {
+            id: BLOAT.to_string(),
+            path: format!("/data/app/{BLOAT}/base.apk"),
+            label: "Bloat".to_string(),
+            size: None,
+            version: None,
+        }
+    }
+
+    fn phone() -> (FakeDevice, DeviceProps) {
+        let mut device = FakeDevice::new(34)
+            .with_user(10, "Work profile")
+            .with_package(BLOAT, "Bloat");
+        let props = prepare_device(&mut device).unwrap();
+        (device, props)
+    }
+
+    #[test]
+    fn uninstall_keeps_a_backup_and_revert_reinstalls() {
+        let (mut device, props) = phone();
+
+        Action::Uninstall(bloat(), UserScope::User(0))
+            .apply_on_device(&mut device, &props)
+            .unwrap();
+        assert!(!device.package(BLOAT).installed.contains(&0));
+        assert!(device.package(BLOAT).installed.contains(&10));
+        assert!(
+            device
+                .files
+                .contains_key(&format!("/data/local/tmp/{BLOAT}/base.apk"))
+        );
+
+        Action::Revert(BLOAT.to_string(), State::Uninstalled, UserScope::User(0))
+            .apply_on_device(&mut device, &props)
+            .unwrap();
+        assert!(device.package(BLOAT).installed.contains(&0));
+        assert!(
+            !device
+                .commands
+                .iter()
+                .any(|command| command.starts_with("pm install "))
+        );
+    }
+
+    #[test]
+    fn uninstall_failure_carries_device_output() {
+        let (mut device, props) = phone();
+        device.script(
+            "pm uninstall",
+            Reply::Print("Failure [DELETE_FAILED_USER_RESTRICTED]".to_string()),
+        );
+
+        let result =
+            Action::Uninstall(bloat(), UserScope::User(0)).apply_on_device(&mut device, &props);
+        match result {
+            Err(ShellRunError::UninstallFailed(id, output)) => {
+                assert_eq!(id, BLOAT);
+                assert!(output.contains("DELETE_FAILED_USER_RESTRICTED"));
             }
+            _ => panic!("expected the uninstall to fail"),
         }
-        Ok(())
+    }
+
+    #[test]
+    fn protected_packages_need_an_override() {
+        let mut device = FakeDevice::new(34)
+            .with_package("com.example.launcher", "Launcher")
+            .with_role(Role::Launcher, "com.example.launcher");
+        let mut props = prepare_device(&mut device).unwrap();
+        let launcher = Action::Disable("com.example.launcher".to_string(), UserScope::User(0));
+
+        assert!(launcher.planned_commands(&props)[0].starts_with("# refused"));
+        let result = launcher.clone().apply_on_device(&mut device, &props);
+        assert!(matches!(
+            result,
+            Err(ShellRunError::Protected(_, Role::Launcher))
+        ));
+        assert!(!device.commands.iter().any(|c| c.starts_with("pm disable")));
+
+        // putting a protected package back is always fine
+        Action::Revert(
+            "com.example.launcher".to_string(),
+            State::Disabled,
+            UserScope::User(0),
+        )
+        .apply_on_device(&mut device, &props)
+        .unwrap();
+
+        props.allow_protected = true;
+        launcher.apply_on_device(&mut device, &props).unwrap();
+        assert!(device.package("com.example.launcher").disabled.contains(&0));
+    }
+
+    #[test]
+    fn uninstall_without_backup_does_not_touch_the_package() {
+        let (mut device, props) = phone();
+        device.script(
+            "rm -rf",
+            Reply::Print("rm: read-only file system".to_string()),
+        );
+
+        let result =
+            Action::Uninstall(bloat(), UserScope::User(0)).apply_on_device(&mut device, &props);
+        assert!(matches!(result, Err(ShellRunError::BackupNotPossible(_))));
+        assert!(device.package(BLOAT).installed.contains(&0));
+    }
+
+    #[test]
+    fn failed_backup_keeps_the_previous_one() {
+        let (mut device, props) = phone();
+        Action::Uninstall(bloat(), UserScope::User(0))
+            .apply_on_device(&mut device, &props)
+            .unwrap();
+        device.script(
+            "pm path",
+            Reply::Print("package:/data/app/gone/base.apk\n".to_string()),
+        );
+
+        let result =
+            Action::Uninstall(bloat(), UserScope::User(10)).apply_on_device(&mut device, &props);
+        assert!(matches!(result, Err(ShellRunError::BackupNotPossible(_))));
+        assert!(device.package(BLOAT).installed.contains(&10));
+        assert!(
+            device
+                .files
+                .contains_key(&format!("/data/local/tmp/{BLOAT}/base.apk"))
+        );
+    }
+
+    #[test]
+    fn disable_and_revert_for_all_users() {
+        let (mut device, props) = phone();
+
+        Action::Disable(BLOAT.to_string(), UserScope::All)
+            .apply_on_device(&mut device, &props)
+            .unwrap();
+        assert_eq!(device.package(BLOAT).disabled.len(), 2);
+
+        Action::Revert(BLOAT.to_string(), State::Disabled, UserScope::All)
+            .apply_on_device(&mut device, &props)
+            .unwrap();
+        assert!(device.package(BLOAT).disabled.is_empty());
+    }
+
+    #[test]
+    fn all_users_skips_users_without_the_package() {
+        let mut device = FakeDevice::new(34)
+            .with_user(10, "Work profile")
+            .with_package(BLOAT, "Bloat")
+            .only_for(BLOAT, 10);
+        let props = prepare_device(&mut device).unwrap();
+
+        Action::Disable(BLOAT.to_string(), UserScope::All)
+            .apply_on_device(&mut device, &props)
+            .unwrap();
+        assert_eq!(device.package(BLOAT).disabled, BTreeSet::from([10]));
+
+        Action::Uninstall(bloat(), UserScope::All)
+            .apply_on_device(&mut device, &props)
+            .unwrap();
+        assert!(device.package(BLOAT).installed.is_empty());
+        assert!(
+            device
+                .commands
+                .contains(&format!("pm path --user 10 {BLOAT}"))
+        );
+        assert!(
+            device
+                .files
+                .contains_key(&format!("/data/local/tmp/{BLOAT}/base.apk"))
+        );
+    }
+
+    #[test]
+    fn all_users_reverts_only_for_users_that_had_the_package() {
+        let mut device = FakeDevice::new(34)
+            .with_user(10, "Work profile")
+            .with_package(BLOAT, "Bloat")
+            .only_for(BLOAT, 10);
+        device.serial = "FAKE-REVERT-ALL".to_string();
+        let props = prepare_device(&mut device).unwrap();
+        let mut batch = journal::Batch::new(&props.serial);
+        let mut apply = |action, known| batch.apply(action, &mut device, &props, known);
+
+        apply(
+            Action::Disable(BLOAT.to_string(), UserScope::All),
+            State::Enabled,
+        )
+        .unwrap();
+        let revert = Action::Revert(BLOAT.to_string(), State::Disabled, UserScope::All);
+        apply(revert, State::Disabled).unwrap();
+
+        apply(Action::Uninstall(bloat(), UserScope::All), State::Enabled).unwrap();
+        let revert = Action::Revert(BLOAT.to_string(), State::Uninstalled, UserScope::All);
+        apply(revert, State::Uninstalled).unwrap();
+        assert_eq!(device.package(BLOAT).installed, BTreeSet::from([10]));
+        assert!(device.package(BLOAT).disabled.is_empty());
+    }
+
+    #[test]
+    fn all_users_revert_needs_a_record_of_the_uninstall() {
+        let (mut device, mut props) = phone();
+        props.serial = "FAKE-NO-RECORD".to_string();
+
+        let revert = Action::Revert(BLOAT.to_string(), State::Uninstalled, UserScope::All);
+        assert!(revert.planned_commands(&props)[0].starts_with("# refused"));
+        let result = revert.apply_on_device(&mut device, &props);
+        assert!(matches!(result, Err(ShellRunError::RevertFailed(..))));
+        assert!(!device.commands.iter().any(|c| c.starts_with("pm install")));
+    }
+
+    #[test]
+    fn revert_restores_split_apks_from_the_backup() {
+        let mut device = FakeDevice::new(34)
+            .with_package(BLOAT, "Bloat")
+            .with_split(BLOAT, "config.en");
+        let props = prepare_device(&mut device).unwrap();
+
+        Action::Uninstall(bloat(), UserScope::User(0))
+            .apply_on_device(&mut device, &props)
+            .unwrap();
+        device.forget(BLOAT);
+
+        Action::Revert(BLOAT.to_string(), State::Uninstalled, UserScope::User(0))
+            .apply_on_device(&mut device, &props)
+            .unwrap();
+        assert!(device.package(BLOAT).installed.contains(&0));
+        let writes = device
+            .commands
+            .iter()
+            .filter(|command| command.starts_with("pm install-write"))
+            .count();
+        assert_eq!(writes, 2);
+    }
+
+    #[test]
+    fn revert_without_any_backup_fails() {
+        let (mut device, props) = phone();
+        device.forget(BLOAT);
+
+        let result = Action::Revert(BLOAT.to_string(), State::Uninstalled, UserScope::User(0))
+            .apply_on_device(&mut device, &props);
+        assert!(matches!(result, Err(ShellRunError::RevertFailed(..))));
+    }
+
+    #[test]
+    fn planned_commands_are_the_ones_that_run() {
+        let (mut device, props) = phone();
+        let action = Action::Disable(BLOAT.to_string(), UserScope::All);
+        let planned = action.planned_commands(&props);
+
+        let before = device.commands.len();
+        action.apply_on_device(&mut device, &props).unwrap();
+        assert_eq!(device.commands[before..], planned);
+    }
+
+    #[test]
+    fn old_devices_block_packages() {
+        let mut device = FakeDevice::new(19).with_package(BLOAT, "Bloat");
+        let props = prepare_device(&mut device).unwrap();
+
+        let planned = Action::Uninstall(bloat(), UserScope::User(0)).planned_commands(&props);
+        assert_eq!(
+            planned.last().unwrap(),
+            &format!("pm block --user 0 {BLOAT}")
+        );
+        let planned = Action::Revert(BLOAT.to_string(), State::Disabled, UserScope::User(0))
+            .planned_commands(&props);
+        assert_eq!(planned, [format!("pm unblock --user 0 {BLOAT}")]);
     }
 }
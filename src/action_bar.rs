@@ -1,5 +1,6 @@
 use crate::Action;
 use crate::categories;
+use crate::health;
 use crate::listview;
 use crate::listview::State;
 
@@ -32,9 +33,17 @@ impl crate::App {
             Button::new("uninstall")
         };
 
+        let Some(session) = self
+            .current_device
+            .as_ref()
+            .and_then(|serial| self.sessions.get_mut(serial))
+        else {
+            return;
+        };
+
         let mut selected: Vec<&listview::Entry> = vec![];
         let mut selected_app_state = 0b111;
-        for entry in self.entries.values().filter(|entry| entry.selected) {
+        for entry in session.entries.values().filter(|entry| entry.selected) {
             selected.push(entry);
             selected_app_state &= entry.state as u8;
         }
@@ -43,36 +52,40 @@ impl crate::App {
 
         ui.separator();
 
+        let mut open_profile = false;
+        let mut open_history = false;
+        let mut reboot = false;
+        let mut select = None;
+        let mut actions: Vec<Action> = vec![];
         ui.horizontal(|ui| {
             let button_size = [80.0, 30.0].into();
-            if self.busy {
+            if session.busy {
                 ui.add_sized(button_size, Spinner::new());
             } else if selected_app_state == State::Enabled as u8 {
                 if add_enabled_button(true, ui, button_size, button) {
                     if self.disable_mode {
                         for entry in selected.iter() {
-                            self.action_tx
-                                .send(Action::Disable(entry.package.id.clone()))
-                                .expect("failed to send message to backend");
+                            actions.push(Action::Disable(
+                                entry.package.id.clone(),
+                                session.user_scope,
+                            ));
                         }
                     } else {
                         for entry in selected.iter() {
-                            self.action_tx
-                                .send(Action::Uninstall(entry.package.clone()))
-                                .expect("failed to send message to backend");
+                            actions
+                                .push(Action::Uninstall(entry.package.clone(), session.user_scope));
                         }
                     }
-                    self.busy = true;
                 }
             } else if selected_app_state == State::Uninstalled as u8 {
                 if add_enabled_button(true, ui, button_size, Button::new("revert")) {
                     for entry in selected.iter() {
-                        self.action_tx
-                            .send(Action::Revert(entry.package.id.clone(), entry.state))
-                            .expect("failed to send message to backend");
+                        actions.push(Action::Revert(
+                            entry.package.id.clone(),
+                            entry.state,
+                            session.user_scope,
+                        ));
                     }
-
-                    self.busy = true;
                 }
             } else {
                 // the selection is a mix of enabled and disabled apps:
@@ -82,15 +95,102 @@ impl crate::App {
 
             ui.checkbox(&mut self.disable_mode, "disable mode")
                 .on_hover_text("prefer disabling apps to uninstalling");
+            ui.checkbox(&mut self.dry_run, "dry run")
+                .on_hover_text("only show the commands actions would run on the device");
+
+            if ui
+                .checkbox(&mut session.pull_backups, "backup to computer")
+                .on_hover_text("also copy the APKs of uninstalled apps to this computer")
+                .changed()
+            {
+                self.pull_backups = session.pull_backups;
+                let _ = session.pull_backups_tx.send(session.pull_backups);
+            }
+
+            if ui
+                .button("open profile")
+                .on_hover_text("apply a state saved with Ctrl S")
+                .clicked()
+            {
+                open_profile = true;
+            }
+
+            let verifying = session.health.as_ref().is_some_and(|report| !report.done());
+            if ui
+                .add_enabled(
+                    !session.busy && !verifying && session.journaled,
+                    Button::new("reboot and verify"),
+                )
+                .on_hover_text(format!(
+                    "reboot, and undo the last batch if the device is not healthy {} min later",
+                    health::BOOT_TIMEOUT.as_secs() / 60
+                ))
+                .on_disabled_hover_text(if session.journaled {
+                    "wait for the current actions or verification to finish"
+                } else {
+                    "zilch has not changed anything on this device yet"
+                })
+                .clicked()
+            {
+                reboot = true;
+            }
+
+            if ui
+                .button("history")
+                .on_hover_text("see and undo what zilch changed on this device")
+                .clicked()
+            {
+                open_history = true;
+            }
 
             ui.separator();
             ui.label(format!("{} selected", selected.len()));
+            ui.menu_button("select", |ui| {
+                for (name, shortcut, command) in SELECT_COMMANDS {
+                    if ui.add(Button::new(name).shortcut_text(shortcut)).clicked() {
+                        select = Some(command);
+                    }
+                }
+            });
             ui.separator();
         });
         ui.add_space(2.0);
+
+        self.confirm_actions(actions);
+        if open_profile {
+            self.open_profile();
+        }
+        if open_history {
+            self.history = Some(Default::default());
+        }
+        if reboot {
+            self.confirm_reboot();
+        }
+        match select {
+            Some(Select::All) => self.select_all_visible(),
+            Some(Select::Invert) => self.invert_selection(),
+            Some(Select::Recommended) => self.select_recommended(),
+            Some(Select::None) => self.clear_selection(),
+            None => {}
+        }
     }
 }
 
+#[derive(Clone, Copy)]
+enum Select {
+    All,
+    Invert,
+    Recommended,
+    None,
+}
+
+const SELECT_COMMANDS: [(&str, &str, Select); 4] = [
+    ("all shown", "Ctrl A", Select::All),
+    ("invert", "Ctrl I", Select::Invert),
+    ("Recommended still enabled", "Ctrl R", Select::Recommended),
+    ("none", "Escape", Select::None),
+];
+
 fn add_enabled_button(enabled: bool, ui: &mut egui::Ui, size: Vec2, button: Button) -> bool {
     ui.add_enabled_ui(enabled, |ui| ui.add_sized(size, button))
         .inner
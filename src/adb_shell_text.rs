@@ -1,18 +1,44 @@
+use std::io::{Read, Write};
+
 use crate::ShellRunError;
-use adb_client::{ADBDeviceExt, ADBUSBDevice};
+use adb_client::{ADBDeviceExt, RebootType, RustADBError};
 
-pub trait ShellCommandText {
+/// What zilch needs from an Android device. Real devices get it from `adb_client`,
+/// tests use a scripted fake.
+pub trait AndroidDevice {
     fn shell_command_text(&mut self, command: &str) -> Result<String, ShellRunError>;
+    fn push_file(&mut self, stream: &mut dyn Read, path: &str) -> Result<(), ShellRunError>;
+    fn pull_file(&mut self, path: &str, output: &mut dyn Write) -> Result<(), ShellRunError>;
+    fn reboot(&mut self) -> Result<(), ShellRunError>;
 }
 
-impl ShellCommandText for ADBUSBDevice {
+fn shell_run_error(e: RustADBError) -> ShellRunError {
+    match e {
+        RustADBError::UsbError(rusb::Error::Timeout) => ShellRunError::Timeout,
+        RustADBError::IOError(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+            ShellRunError::Timeout
+        }
+        _ => ShellRunError::Unrecoverable,
+    }
+}
+
+impl<T: ADBDeviceExt + ?Sized> AndroidDevice for T {
     fn shell_command_text(&mut self, command: &str) -> Result<String, ShellRunError> {
         let mut buf = Vec::with_capacity(4096);
         self.shell_command(&[command], &mut buf)
-            .map_err(|e| match e {
-                adb_client::RustADBError::UsbError(rusb::Error::Timeout) => ShellRunError::Timeout,
-                _ => ShellRunError::Unrecoverable,
-            })?;
+            .map_err(shell_run_error)?;
         String::from_utf8(buf).map_err(|_| ShellRunError::ParseError)
     }
+
+    fn push_file(&mut self, stream: &mut dyn Read, path: &str) -> Result<(), ShellRunError> {
+        self.push(stream, &path).map_err(shell_run_error)
+    }
+
+    fn pull_file(&mut self, path: &str, output: &mut dyn Write) -> Result<(), ShellRunError> {
+        self.pull(&path, output).map_err(shell_run_error)
+    }
+
+    fn reboot(&mut self) -> Result<(), ShellRunError> {
+        ADBDeviceExt::reboot(self, RebootType::System).map_err(shell_run_error)
+    }
 }
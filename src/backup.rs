@@ -0,0 +1,137 @@
+use crate::adb_shell_text::AndroidDevice;
+use crate::device::DeviceProps;
+use crate::host_store;
+use crate::{PackageIdentifier, ShellRunError};
+
+/// Where the APKs of a package are copied to on the device before it is uninstalled.
+pub fn backup_dir(id: &str) -> String {
+    format!("/data/local/tmp/{id}")
+}
+
+/// Single APK backups made before split APKs were handled.
+fn legacy_backup_path(id: &str) -> String {
+    format!("/data/local/tmp/{id}.apk")
+}
+
+fn path_command(id: &str, user: u32) -> String {
+    format!("pm path --user {user} {id}")
+}
+
+/// Copies next to the previous backup first, which is only replaced once every APK made it.
+fn copy_command(id: &str, paths: &str) -> String {
+    let dir = backup_dir(id);
+    let new_dir = format!("{dir}.new");
+    format!(
+        "rm -rf {new_dir} && mkdir -p {new_dir} && cp {paths} {new_dir}/ && rm -rf {dir} && mv {new_dir} {dir}"
+    )
+}
+
+/// The commands `back_up` runs, for dry runs.
+pub fn planned_back_up(id: &str, user: u32) -> Vec<String> {
+    vec![
+        path_command(id, user),
+        copy_command(id, "<paths printed by pm path>"),
+    ]
+}
+
+/// Copies the base APK and every split of a package to its backup directory.
+pub fn back_up<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+    id: &PackageIdentifier,
+    user: u32,
+) -> Result<(), ShellRunError> {
+    let raw_paths_text = device.shell_command_text(&path_command(id, user))?;
+    let paths: Vec<&str> = raw_paths_text
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("package:"))
+        .collect();
+    if paths.is_empty() {
+        return Err(ShellRunError::BackupNotPossible(id.clone()));
+    }
+
+    let output = device.shell_command_text(&copy_command(id, &paths.join(" ")))?;
+    // cp is silent unless something went wrong
+    if !output.trim().is_empty() {
+        return Err(ShellRunError::BackupNotPossible(id.clone()));
+    }
+    Ok(())
+}
+
+/// Lists the APKs in a package's backup directory along with their sizes.
+pub fn backed_up_apks<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+    id: &PackageIdentifier,
+) -> Result<Vec<(u64, String)>, ShellRunError> {
+    let dir = backup_dir(id);
+    let raw_stat_text = device.shell_command_text(&format!("stat -c '%s %n' {dir}/*.apk"))?;
+    Ok(raw_stat_text
+        .lines()
+        .filter_map(|line| {
+            let (size, path) = line.trim().split_once(' ')?;
+            Some((size.parse().ok()?, path.to_string()))
+        })
+        .collect())
+}
+
+/// Reinstalls a package from its backup. Split APKs are installed together in one session.
+/// When the device lost its copy, the one pulled to this computer is pushed back first.
+pub fn restore<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+    props: &DeviceProps,
+    id: &PackageIdentifier,
+    user: u32,
+) -> Result<(), ShellRunError> {
+    let mut apks = backed_up_apks(device, id)?;
+    if apks.is_empty() && host_store::push(device, props, id)? {
+        apks = backed_up_apks(device, id)?;
+    }
+
+    let output = match apks.as_slice() {
+        [] => device.shell_command_text(&format!(
+            "pm install -r --user {user} {}",
+            legacy_backup_path(id)
+        ))?,
+        [(_, path)] => device.shell_command_text(&format!("pm install -r --user {user} {path}"))?,
+        _ => install_session(device, id, user, &apks)?,
+    };
+
+    if !output.contains("Success") {
+        return Err(ShellRunError::RevertFailed(id.clone(), output));
+    }
+    Ok(())
+}
+
+fn install_session<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+    id: &PackageIdentifier,
+    user: u32,
+    apks: &[(u64, String)],
+) -> Result<String, ShellRunError> {
+    let total_size: u64 = apks.iter().map(|(size, _)| size).sum();
+    let output = device.shell_command_text(&format!(
+        "pm install-create -r --user {user} -S {total_size}"
+    ))?;
+
+    // Success: created install session [1234567]
+    let Some(session) = output
+        .split_once('[')
+        .and_then(|(_, rest)| rest.split_once(']'))
+        .map(|(session, _)| session.to_string())
+    else {
+        return Err(ShellRunError::RevertFailed(id.clone(), output));
+    };
+
+    for (size, path) in apks.iter() {
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        let split_name = file_name.strip_suffix(".apk").unwrap_or(file_name);
+        let output = device.shell_command_text(&format!(
+            "pm install-write -S {size} {session} {split_name} {path}"
+        ))?;
+        if !output.contains("Success") {
+            device.shell_command_text(&format!("pm install-abandon {session}"))?;
+            return Err(ShellRunError::RevertFailed(id.clone(), output));
+        }
+    }
+
+    device.shell_command_text(&format!("pm install-commit {session}"))
+}
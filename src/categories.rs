@@ -13,6 +13,13 @@ pub const NAMES: [&str; 5] = [
     "Unidentified",
 ];
 
+pub fn name_to_value(name: &str) -> Option<u8> {
+    NAMES
+        .iter()
+        .position(|&known| known == name)
+        .map(|index| VALUES[index])
+}
+
 pub fn value_to_name(value: u8) -> &'static str {
     match value {
         RECOMMENDED => "Recommended",
@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::action::Action;
+use crate::listview::{Entry, State};
+use crate::profile::Profile;
+use crate::users::UserScope;
+use crate::{
+    categories, device, dry_run, fetch_packages, journal, lists, overrides, prepare_device,
+    reconcile,
+};
+
+const USAGE: &str = "usage: zilch
+       zilch [--serial <serial> | --connect <host:port>] [--user <id|all>]
+             [--pull-backups] [--dry-run] [--lists <uad_lists.json>]
+             [--overrides <file.json>] [--allow-protected] COMMAND
+
+Without any arguments, zilch starts the graphical interface, which
+takes no options. With them, it runs COMMAND without a window.
+Commands use the USB device unless --connect names a device
+in wireless debugging or adb tcpip mode. With several USB devices
+attached, --serial picks one. They act on user 0
+unless --user names another Android user or all of them.
+With --pull-backups, uninstalled APKs are also copied to this computer.
+With --dry-run, the shell commands are printed instead of run.
+With --lists, package descriptions and categories from a Universal
+Android Debloater list take precedence over the built-in ones.
+With --overrides, a team's ratings, notes and tags win over both.
+Packages the system needs, and the current launcher, keyboard, dialer
+and SMS app, are never uninstalled or disabled without --allow-protected.
+
+commands:
+    list [--json]            list the packages on the device
+    uninstall <package>...   uninstall packages, keeping a backup on the device
+    disable <package>...     disable packages
+    revert <package>...      restore uninstalled or disabled packages
+    apply <profile.ini>      bring the device in line with a saved profile
+    help                     print this message";
+
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_ACTION_FAILED: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_NO_DEVICE: i32 = 3;
+
+enum Command {
+    List { json: bool },
+    Uninstall(Vec<String>),
+    Disable(Vec<String>),
+    Revert(Vec<String>),
+    Apply(String),
+}
+
+#[derive(Serialize)]
+struct ListedPackage<'a> {
+    id: &'a str,
+    label: &'a str,
+    path: &'a str,
+    state: &'static str,
+    category: &'static str,
+    description: Option<&'static str>,
+    source: Option<String>,
+    note: Option<&'static str>,
+    tags: &'a [String],
+}
+
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    let (command, rest) = args.split_first().ok_or("missing command")?;
+    let packages = || {
+        if rest.is_empty() {
+            Err(format!("{command} needs at least one package"))
+        } else {
+            Ok(rest.to_vec())
+        }
+    };
+
+    match command.as_str() {
+        "list" => match rest {
+            [] => Ok(Command::List { json: false }),
+            [flag] if flag == "--json" => Ok(Command::List { json: true }),
+            _ => Err("list only accepts --json".to_string()),
+        },
+        "uninstall" => packages().map(Command::Uninstall),
+        "disable" => packages().map(Command::Disable),
+        "revert" => packages().map(Command::Revert),
+        "apply" => match rest {
+            [path] => Ok(Command::Apply(path.clone())),
+            _ => Err("apply takes exactly one profile".to_string()),
+        },
+        unknown => Err(format!("unknown command {unknown}")),
+    }
+}
+
+/// Runs zilch without a window and returns the process exit code.
+pub fn run(args: &[String]) -> i32 {
+    if matches!(args[0].as_str(), "help" | "--help" | "-h") {
+        println!("{USAGE}");
+        return EXIT_SUCCESS;
+    }
+
+    let mut address = None;
+    let mut serial = None;
+    let mut users = UserScope::default();
+    let mut pull_backups = false;
+    let mut dry_run = false;
+    let mut allow_protected = false;
+    let mut lists_path = None;
+    let mut overrides_path = None;
+    let mut args = args;
+    loop {
+        match args {
+            [flag, rest @ ..] if flag == "--dry-run" => {
+                dry_run = true;
+                args = rest;
+            }
+            [flag, rest @ ..] if flag == "--allow-protected" => {
+                allow_protected = true;
+                args = rest;
+            }
+            [flag, rest @ ..] if flag == "--pull-backups" => {
+                pull_backups = true;
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "--lists" => {
+                lists_path = Some(PathBuf::from(value));
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "--overrides" => {
+                overrides_path = Some(PathBuf::from(value));
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "--serial" => {
+                serial = Some(value.as_str());
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "--connect" => {
+                address = Some(value);
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "--user" => {
+                let Some(scope) = UserScope::parse(value) else {
+                    eprintln!("invalid user {value}\n\n{USAGE}");
+                    return EXIT_USAGE;
+                };
+                users = scope;
+                args = rest;
+            }
+            _ => break,
+        }
+    }
+
+    lists::load(lists_path.as_deref());
+    overrides::load(overrides_path.as_deref());
+
+    let command = match parse_args(args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return EXIT_USAGE;
+        }
+    };
+
+    let connected = match address {
+        Some(address) => device::connect_tcp(address).map(|(device, _)| device),
+        None => device::connect_usb_serial(serial),
+    };
+    let mut device = match connected {
+        Ok(device) => device,
+        Err(e) => {
+            eprintln!("no device found: {e}");
+            return EXIT_NO_DEVICE;
+        }
+    };
+
+    let props = match prepare_device(device.as_mut()) {
+        Ok(props) => device::DeviceProps {
+            pull_backups,
+            allow_protected,
+            ..props
+        },
+        Err(e) => {
+            eprintln!("failed to prepare the device: {e}");
+            return EXIT_ACTION_FAILED;
+        }
+    };
+
+    let mut entries = BTreeMap::new();
+    match fetch_packages(
+        device.as_mut(),
+        &props,
+        users,
+        &Default::default(),
+        &Default::default(),
+    ) {
+        Ok((diff, _, _)) => reconcile(&mut entries, diff),
+        Err(e) => {
+            eprintln!("{e}");
+            return EXIT_ACTION_FAILED;
+        }
+    }
+
+    let actions = match command {
+        Command::List { json } => return list(&entries, json),
+        Command::Uninstall(ids) => {
+            let mut actions = vec![];
+            for id in ids {
+                match entries.get(&id) {
+                    Some(entry) if entry.state != State::Uninstalled => {
+                        actions.push(Action::Uninstall(entry.package.clone(), users))
+                    }
+                    _ => {
+                        eprintln!("package {id} is not installed");
+                        return EXIT_ACTION_FAILED;
+                    }
+                }
+            }
+            actions
+        }
+        Command::Disable(ids) => ids
+            .into_iter()
+            .map(|id| Action::Disable(id, users))
+            .collect(),
+        Command::Revert(ids) => ids
+            .into_iter()
+            .filter_map(|id| match entries.get(&id).map(|entry| entry.state) {
+                Some(State::Enabled) => {
+                    println!("{id} is already enabled");
+                    None
+                }
+                Some(state) => Some(Action::Revert(id, state, users)),
+                // packages uninstalled before this run are not listed at all
+                None => Some(Action::Revert(id, State::Uninstalled, users)),
+            })
+            .collect(),
+        Command::Apply(path) => {
+            let profile = match std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| Profile::parse(&text).map_err(|e| e.to_string()))
+            {
+                Ok(profile) => profile,
+                Err(e) => {
+                    eprintln!("failed to load profile {path}: {e}");
+                    return EXIT_USAGE;
+                }
+            };
+            let plan = profile.diff(&props.serial, &entries);
+            if plan.missing > 0 {
+                eprintln!(
+                    "{} packages in the profile are not present on this device",
+                    plan.missing
+                );
+            }
+            plan.changes
+                .iter()
+                .flat_map(|change| change.actions(&entries[&change.id].package, users))
+                .collect()
+        }
+    };
+
+    if dry_run {
+        for line in dry_run::plan(&actions, &props) {
+            println!("{line}");
+        }
+        return EXIT_SUCCESS;
+    }
+
+    let mut exit_code = EXIT_SUCCESS;
+    let mut batch = journal::Batch::new(&props.serial);
+    for action in actions {
+        let description = action.to_string();
+        let known = entries
+            .get(action.package_id())
+            .map(|entry| entry.state)
+            .unwrap_or(State::Uninstalled);
+        match batch.apply(action, device.as_mut(), &props, known) {
+            Ok(()) => println!("{description}: ok"),
+            Err(e) => {
+                match e.output() {
+                    Some(output) => eprintln!("{e}: {output}"),
+                    None => eprintln!("{e}"),
+                }
+                exit_code = EXIT_ACTION_FAILED;
+            }
+        }
+    }
+    exit_code
+}
+
+fn list(entries: &BTreeMap<String, Entry>, json: bool) -> i32 {
+    let listed: Vec<ListedPackage> = entries
+        .values()
+        .map(|entry| ListedPackage {
+            id: &entry.package.id,
+            label: &entry.package.label,
+            path: &entry.package.path,
+            state: entry.state.name(),
+            category: categories::value_to_name(entry.removal()),
+            description: entry
+                .overrides
+                .and_then(|o| o.description.as_deref())
+                .or(entry.metadata.map(|m| m.description)),
+            source: entry.source.map(|source| source.to_string()),
+            note: entry.overrides.and_then(|o| o.note.as_deref()),
+            tags: entry.tags(),
+        })
+        .collect();
+
+    if json {
+        match serde_json::to_string_pretty(&listed) {
+            Ok(text) => println!("{text}"),
+            Err(e) => {
+                eprintln!("failed to serialize package list: {e}");
+                return EXIT_ACTION_FAILED;
+            }
+        }
+    } else {
+        for package in listed {
+            println!(
+                "{}\t{}\t{}\t{}",
+                package.state, package.category, package.id, package.label
+            );
+        }
+    }
+    EXIT_SUCCESS
+}
@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+
+use egui::{Id, Modal, RichText, ScrollArea, TextEdit};
+
+use crate::Action;
+use crate::categories;
+use crate::health;
+use crate::journal;
+use crate::listview::Entry;
+use crate::protected::Role;
+
+/// What has to be typed to go ahead with a batch touching Unsafe packages.
+pub const UNSAFE_PHRASE: &str = "I understand";
+
+/// Uninstalls and disables, or a reboot and verify, waiting for the user to look them over.
+pub struct Confirmation {
+    /// The device the actions are meant for.
+    pub serial: String,
+    pub actions: Vec<Action>,
+    /// Asks to reboot and verify instead, `actions` being what undoes the last batch if the
+    /// device does not come back healthy.
+    pub reboot: bool,
+    typed: String,
+    allow_protected: bool,
+}
+
+impl Confirmation {
+    pub fn new(serial: String, actions: Vec<Action>) -> Self {
+        Confirmation {
+            serial,
+            actions,
+            reboot: false,
+            typed: String::new(),
+            allow_protected: false,
+        }
+    }
+
+    pub fn reboot(serial: String, undo: Vec<Action>) -> Self {
+        Confirmation {
+            reboot: true,
+            ..Confirmation::new(serial, undo)
+        }
+    }
+}
+
+/// Reverts put things back, everything else takes something away.
+pub fn needs_confirmation(actions: &[Action]) -> bool {
+    actions
+        .iter()
+        .any(|action| !matches!(action, Action::Revert(..)))
+}
+
+/// The actions grouped by the removal category of their package, safest first.
+pub fn by_category<'a>(
+    actions: &'a [Action],
+    entries: &BTreeMap<String, Entry>,
+) -> Vec<(u8, Vec<&'a Action>)> {
+    categories::VALUES
+        .into_iter()
+        .map(|category| {
+            let grouped = actions
+                .iter()
+                .filter(|action| {
+                    let removal = entries
+                        .get(action.package_id())
+                        .map(Entry::removal)
+                        .unwrap_or(categories::UNIDENTIFIED);
+                    removal == category
+                })
+                .collect();
+            (category, grouped)
+        })
+        .filter(|(_, grouped): &(u8, Vec<&Action>)| !grouped.is_empty())
+        .collect()
+}
+
+impl crate::App {
+    /// Queues actions, asking first if they remove anything.
+    pub fn confirm_actions(&mut self, actions: Vec<Action>) {
+        // a dry run changes nothing, so there is nothing to confirm
+        if self.dry_run || !needs_confirmation(&actions) {
+            self.queue_actions(actions);
+        } else if let Some(serial) = self.current_device.clone() {
+            self.pending_confirmation = Some(Confirmation::new(serial, actions));
+        }
+    }
+
+    /// Asks before rebooting the current device, showing what a failed verification undoes.
+    pub fn confirm_reboot(&mut self) {
+        let Some(serial) = self.current_device.clone() else {
+            return;
+        };
+        let records = journal::read(&serial);
+        let undo = records
+            .last()
+            .map(|last| health::rollback(&records, last.batch))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(action, _)| action)
+            .collect();
+        self.pending_confirmation = Some(Confirmation::reboot(serial, undo));
+    }
+
+    pub fn confirmation_dialog(&mut self, ctx: &egui::Context) {
+        if let Some(confirmation) = self.pending_confirmation.as_ref()
+            && self.current_device.as_ref() != Some(&confirmation.serial)
+        {
+            self.notices.push(format!(
+                "{} is no longer the current device, nothing was changed on it",
+                confirmation.serial
+            ));
+            self.pending_confirmation = None;
+            return;
+        }
+        let Some(session) = self
+            .current_device
+            .as_ref()
+            .and_then(|serial| self.sessions.get(serial))
+        else {
+            return;
+        };
+        let Some(confirmation) = self.pending_confirmation.as_mut() else {
+            return;
+        };
+
+        let groups = by_category(&confirmation.actions, &session.entries);
+        let props = session.props();
+        let reboot = confirmation.reboot;
+        // undoing a batch is up to the worker, there is nothing to allow
+        let protected: Vec<(&Action, Role)> = confirmation
+            .actions
+            .iter()
+            .filter(|_| !reboot)
+            .filter_map(|action| Some((action, action.protection(&props)?)))
+            .collect();
+        let unsafe_included = !reboot
+            && (groups
+                .iter()
+                .any(|(category, _)| *category == categories::UNSAFE)
+                || (confirmation.allow_protected && !protected.is_empty()));
+
+        let mut confirm = false;
+        let mut close = false;
+        let modal = Modal::new(Id::new("confirmation_dialog")).show(ctx, |ui| {
+            if reboot {
+                ui.heading(format!("Reboot {} and verify?", session.info.model));
+                ui.label(if confirmation.actions.is_empty() {
+                    "The last batch left nothing to undo if the device does not come back healthy."
+                        .to_string()
+                } else {
+                    format!(
+                        "If it is not healthy {} min later, zilch undoes the last batch:",
+                        health::BOOT_TIMEOUT.as_secs() / 60
+                    )
+                });
+            } else {
+                ui.heading(format!(
+                    "Change {} packages on {}?",
+                    confirmation.actions.len(),
+                    session.info.model
+                ));
+            }
+            ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                for (category, actions) in groups.iter() {
+                    let color = match *category {
+                        categories::UNSAFE => Some(ui.style().visuals.error_fg_color),
+                        categories::UNIDENTIFIED => Some(ui.style().visuals.warn_fg_color),
+                        _ => None,
+                    };
+                    let mut heading = RichText::new(format!(
+                        "{} ({})",
+                        categories::value_to_name(*category),
+                        actions.len()
+                    ))
+                    .strong();
+                    if let Some(color) = color {
+                        heading = heading.color(color);
+                    }
+                    ui.add_space(4.0);
+                    ui.label(heading);
+
+                    for action in actions.iter() {
+                        let entry = session.entries.get(action.package_id());
+                        let label = entry.map(|e| e.package.label.as_str()).unwrap_or_default();
+                        ui.label(RichText::new(format!("{action} {label}")).size(12.0));
+                        // the safe categories are what they say, the rest deserve a second look
+                        if color.is_some()
+                            && let Some(description) = entry
+                                .and_then(|e| e.metadata)
+                                .map(|m| m.description)
+                                .filter(|d| !d.is_empty())
+                        {
+                            ui.label(RichText::new(description).weak().size(10.0));
+                        }
+                    }
+                }
+            });
+
+            ui.separator();
+            if !protected.is_empty() {
+                let error_color = ui.style().visuals.error_fg_color;
+                ui.label(RichText::new("Protected").strong().color(error_color));
+                for (action, role) in protected.iter() {
+                    ui.label(
+                        RichText::new(format!("{} is {role}", action.package_id()))
+                            .color(error_color)
+                            .size(12.0),
+                    );
+                }
+                ui.checkbox(
+                    &mut confirmation.allow_protected,
+                    "remove protected packages anyway",
+                )
+                .on_hover_text("otherwise they are skipped and listed as failed");
+                ui.separator();
+            }
+            if unsafe_included {
+                ui.label(
+                    RichText::new(format!(
+                        "Unsafe and protected packages can stop the phone from booting. Type \"{UNSAFE_PHRASE}\" to go ahead."
+                    ))
+                    .color(ui.style().visuals.error_fg_color),
+                );
+                ui.add(TextEdit::singleline(&mut confirmation.typed).hint_text(UNSAFE_PHRASE));
+            }
+            let confirmed = !unsafe_included || confirmation.typed.trim() == UNSAFE_PHRASE;
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(confirmed, egui::Button::new("go ahead"))
+                    .clicked()
+                {
+                    confirm = true;
+                }
+                if ui.button("cancel").clicked() {
+                    close = true;
+                }
+            });
+        });
+        close |= modal.should_close();
+
+        if confirm && let Some(confirmation) = self.pending_confirmation.take() {
+            if confirmation.reboot {
+                if let Some(session) = self.session_mut() {
+                    let _ = session.reboot_tx.send(());
+                }
+            } else {
+                self.queue_actions_allowing(confirmation.actions, confirmation.allow_protected);
+            }
+        } else if close {
+            self.pending_confirmation = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Metadata;
+    use crate::listview::State;
+    use crate::users::UserScope;
+
+    static UNSAFE: Metadata = Metadata {
+        description: "Needed to boot",
+        removal: categories::UNSAFE,
+    };
+    static RECOMMENDED: Metadata = Metadata {
+        description: "Bloat",
+        removal: categories::RECOMMENDED,
+    };
+
+    fn entry(id: &str, metadata: Option<&'static Metadata>) -> (String, Entry) {
+        let entry = Entry {
+            metadata,
+            ..Entry::test(id, State::Enabled)
+        };
+        (id.to_string(), entry)
+    }
+
+    #[test]
+    fn groups_actions_by_category() {
+        let entries: BTreeMap<String, Entry> = [
+            entry("core", Some(&UNSAFE)),
+            entry("bloat", Some(&RECOMMENDED)),
+            entry("unknown", None),
+        ]
+        .into_iter()
+        .collect();
+        let actions: Vec<Action> = ["core", "unknown", "bloat"]
+            .into_iter()
+            .map(|id| Action::Disable(id.to_string(), UserScope::default()))
+            .collect();
+
+        let groups: Vec<(u8, Vec<&str>)> = by_category(&actions, &entries)
+            .into_iter()
+            .map(|(category, actions)| {
+                let ids = actions.iter().map(|a| a.package_id().as_str()).collect();
+                (category, ids)
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                (categories::RECOMMENDED, vec!["bloat"]),
+                (categories::UNSAFE, vec!["core"]),
+                (categories::UNIDENTIFIED, vec!["unknown"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_reverts_go_ahead_unconfirmed() {
+        let users = UserScope::default();
+        let revert = Action::Revert("a".to_string(), State::Disabled, users);
+        let disable = Action::Disable("a".to_string(), users);
+        assert!(!needs_confirmation(std::slice::from_ref(&revert)));
+        assert!(needs_confirmation(&[revert, disable]));
+    }
+}
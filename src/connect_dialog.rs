@@ -0,0 +1,169 @@
+use std::net::SocketAddrV4;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread::spawn;
+
+use adb_client::ADBServer;
+use egui::{Button, Id, Modal, RichText, TextEdit};
+
+use crate::device;
+
+/// Dialog for reaching devices over the network, either in `adb tcpip` mode
+/// or through Android 11+ wireless debugging.
+pub struct ConnectDialog {
+    address: String,
+    pairing_address: String,
+    pairing_code: String,
+    status: String,
+    status_tx: Sender<String>,
+    status_rx: Receiver<String>,
+    /// Whether an adb server answered, which pairing goes through. None while still asking.
+    adb_server: Option<Result<(), String>>,
+    adb_server_rx: Receiver<Result<(), String>>,
+}
+
+impl ConnectDialog {
+    pub fn new(ctx: &egui::Context) -> Self {
+        let (status_tx, status_rx) = channel();
+        let (adb_server_tx, adb_server_rx) = channel();
+        let ctx = ctx.clone();
+        // starts the server when adb is installed but not running yet
+        spawn(move || {
+            let version = ADBServer::default().version();
+            let _ = adb_server_tx.send(version.map(|_| ()).map_err(|e| e.to_string()));
+            ctx.request_repaint();
+        });
+        ConnectDialog {
+            address: String::default(),
+            pairing_address: String::default(),
+            pairing_code: String::default(),
+            status: String::default(),
+            status_tx,
+            status_rx,
+            adb_server: None,
+            adb_server_rx,
+        }
+    }
+}
+
+impl crate::App {
+    pub fn connect_dialog(&mut self, ctx: &egui::Context) {
+        let Some(dialog) = self.connect_dialog.as_mut() else {
+            return;
+        };
+
+        while let Ok(status) = dialog.status_rx.try_recv() {
+            dialog.status = status;
+        }
+        if let Ok(adb_server) = dialog.adb_server_rx.try_recv() {
+            dialog.adb_server = Some(adb_server);
+        }
+
+        let mut close = false;
+        let modal = Modal::new(Id::new("connect_dialog")).show(ctx, |ui| {
+            ui.heading("Connect over the network");
+
+            ui.label("Wireless debugging pairing (Android 11+)");
+            ui.horizontal(|ui| {
+                ui.add(
+                    TextEdit::singleline(&mut dialog.pairing_address)
+                        .hint_text("ip:port")
+                        .desired_width(160.0),
+                );
+                ui.add(
+                    TextEdit::singleline(&mut dialog.pairing_code)
+                        .hint_text("pairing code")
+                        .desired_width(100.0),
+                );
+                let pair_button =
+                    ui.add_enabled(dialog.adb_server == Some(Ok(())), Button::new("pair"));
+                if pair_button.clicked() {
+                    dialog.status = "pairing…".to_string();
+                    pair(
+                        dialog.pairing_address.trim(),
+                        dialog.pairing_code.trim(),
+                        dialog.status_tx.clone(),
+                        ui.ctx().clone(),
+                    );
+                }
+            });
+            match dialog.adb_server.as_ref() {
+                None => {
+                    ui.label(
+                        RichText::new("looking for an adb server…")
+                            .weak()
+                            .size(10.0),
+                    );
+                }
+                Some(Ok(())) => {}
+                Some(Err(e)) => {
+                    ui.label(
+                        RichText::new(format!(
+                            "pairing needs adb installed, no adb server could be reached: {e}"
+                        ))
+                        .color(ui.style().visuals.warn_fg_color)
+                        .size(10.0),
+                    );
+                }
+            }
+
+            ui.separator();
+            ui.label("Device address");
+            ui.horizontal(|ui| {
+                ui.add(
+                    TextEdit::singleline(&mut dialog.address)
+                        .hint_text("host:port")
+                        .desired_width(160.0),
+                );
+                if ui.button("connect").clicked() {
+                    dialog.status = "connecting…".to_string();
+                    let address = dialog.address.trim().to_string();
+                    let status_tx = dialog.status_tx.clone();
+                    let session_tx = self.session_tx.clone();
+                    let ctx = ui.ctx().clone();
+                    spawn(move || match device::connect_tcp(&address) {
+                        Ok((device, transport)) => {
+                            let _ = status_tx.send(format!("connected to {address}"));
+                            device::tcp_thread(address, device, transport, session_tx, ctx);
+                        }
+                        Err(e) => {
+                            let _ = status_tx.send(format!("failed to connect: {e}"));
+                            ctx.request_repaint();
+                        }
+                    });
+                }
+            });
+
+            if !dialog.status.is_empty() {
+                ui.label(&dialog.status);
+            }
+
+            ui.separator();
+            if ui.button("close").clicked() {
+                close = true;
+            }
+        });
+
+        if close || modal.should_close() {
+            self.connect_dialog = None;
+        }
+    }
+}
+
+fn pair(address: &str, code: &str, status_tx: Sender<String>, ctx: egui::Context) {
+    let address = match address.parse::<SocketAddrV4>() {
+        Ok(address) => address,
+        Err(e) => {
+            let _ = status_tx.send(format!("invalid pairing address {address}: {e}"));
+            return;
+        }
+    };
+    let code = code.to_string();
+    spawn(move || {
+        let status = match ADBServer::default().pair(address, code) {
+            Ok(()) => format!("paired with {address}, now connect to the address on the device"),
+            Err(e) => format!("failed to pair: {e}"),
+        };
+        let _ = status_tx.send(status);
+        ctx.request_repaint();
+    });
+}
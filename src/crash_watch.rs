@@ -0,0 +1,432 @@
+use std::fmt::Display;
+
+use egui::{Color32, RichText, ScrollArea, TopBottomPanel};
+
+use crate::action::Action;
+use crate::adb_shell_text::AndroidDevice;
+use crate::journal::{self, Record, since_epoch};
+use crate::listview::State;
+use crate::users::UserScope;
+use crate::{PackageIdentifier, ShellRunError};
+
+/// How long after a removal crashes are blamed on it.
+const RECENT_SECS: u64 = 60 * 60;
+/// Crashes kept for the frontend, oldest dropped first.
+pub const KEPT: usize = 50;
+/// Lines of a crash kept to show along with it.
+const KEPT_LINES: usize = 12;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Fatal,
+    Anr,
+    /// A lookup of a package that is not there, which the app may or may not survive.
+    MissingPackage,
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Kind::Fatal => "crash",
+            Kind::Anr => "not responding",
+            Kind::MissingPackage => "missing package",
+        })
+    }
+}
+
+/// Something going wrong in logcat.
+pub struct Event {
+    pub kind: Kind,
+    /// Milliseconds since the unix epoch, by the device's clock.
+    pub time: u64,
+    /// The process that crashed, or the log tag when logcat does not say.
+    pub process: String,
+    pub lines: Vec<String>,
+}
+
+/// A package removed lately, which a crash may be down to.
+pub struct Suspect {
+    pub id: PackageIdentifier,
+    pub state: State,
+    pub users: UserScope,
+    /// Whether the crash names the package, rather than only following its removal.
+    pub mentioned: bool,
+}
+
+impl Suspect {
+    pub fn revert(&self) -> Action {
+        Action::Revert(self.id.clone(), self.state, self.users)
+    }
+}
+
+pub struct Crash {
+    pub event: Event,
+    pub suspects: Vec<Suspect>,
+}
+
+/// A line of `logcat -v threadtime -v epoch`: `sssss.mmm  pid  tid level tag: message`.
+/// Unlike the default `MM-DD` timestamps these still sort right across the new year.
+struct Line<'a> {
+    time: u64,
+    pid: &'a str,
+    tag: &'a str,
+    message: &'a str,
+}
+
+/// Reads `sssss.mmm` as milliseconds.
+pub fn epoch_millis(time: &str) -> Option<u64> {
+    let (seconds, millis) = time.split_once('.')?;
+    Some(seconds.parse::<u64>().ok()? * 1000 + millis.parse::<u64>().ok()?)
+}
+
+fn parse_line(line: &str) -> Option<Line<'_>> {
+    let (time, rest) = line.trim_start().split_once(' ')?;
+    let time = epoch_millis(time)?;
+    let (pid, rest) = rest.trim_start().split_once(' ')?;
+    let (_tid, rest) = rest.trim_start().split_once(' ')?;
+    let (_level, rest) = rest.trim_start().split_once(' ')?;
+    let (tag, message) = rest.split_once(':')?;
+    Some(Line {
+        time,
+        pid,
+        tag: tag.trim(),
+        message: message.trim(),
+    })
+}
+
+/// Picks fatal exceptions, ANRs and failed package lookups out of logcat lines.
+pub fn parse(text: &str) -> Vec<Event> {
+    let mut events: Vec<Event> = vec![];
+    // the pid of the fatal exception whose stack trace is being read
+    let mut fatal_pid = None;
+    for raw in text.lines() {
+        let Some(line) = parse_line(raw) else {
+            continue;
+        };
+
+        if line.tag == "AndroidRuntime" && line.message.starts_with("FATAL EXCEPTION") {
+            fatal_pid = Some(line.pid.to_string());
+            events.push(Event {
+                kind: Kind::Fatal,
+                time: line.time,
+                process: line.tag.to_string(),
+                lines: vec![line.message.to_string()],
+            });
+            continue;
+        }
+        if line.tag == "AndroidRuntime"
+            && fatal_pid.as_deref() == Some(line.pid)
+            && let Some(event) = events.last_mut()
+        {
+            if let Some(process) = line
+                .message
+                .strip_prefix("Process: ")
+                .and_then(|rest| rest.split(',').next())
+            {
+                event.process = process.to_string();
+            }
+            if event.lines.len() < KEPT_LINES {
+                event.lines.push(line.message.to_string());
+            }
+            continue;
+        }
+        fatal_pid = None;
+
+        if let Some(rest) = line.message.strip_prefix("ANR in ") {
+            events.push(Event {
+                kind: Kind::Anr,
+                time: line.time,
+                process: rest.split_whitespace().next().unwrap_or(rest).to_string(),
+                lines: vec![line.message.to_string()],
+            });
+        } else if line.message.contains("NameNotFoundException")
+            || line.message.contains("Package not found")
+        {
+            events.push(Event {
+                kind: Kind::MissingPackage,
+                time: line.time,
+                process: line.tag.to_string(),
+                lines: vec![line.message.to_string()],
+            });
+        }
+    }
+    events
+}
+
+/// Whether `text` names the package, and not merely another package starting the same way.
+fn mentions(text: &str, id: &str) -> bool {
+    text.match_indices(id).any(|(start, _)| {
+        let part_of_name = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+        let before = text[..start].chars().next_back();
+        let after = text[start + id.len()..].chars().next();
+        !before.is_some_and(part_of_name) && !after.is_some_and(|c| part_of_name(c) && c != '.')
+    })
+}
+
+/// Packages uninstalled or disabled in the last hour according to the journal, newest first.
+pub fn recent_removals(records: &[Record]) -> Vec<Suspect> {
+    let since = since_epoch().as_secs().saturating_sub(RECENT_SECS);
+    let mut suspects: Vec<Suspect> = vec![];
+    for record in records.iter().rev() {
+        if record.time < since || record.error.is_some() {
+            continue;
+        }
+        if suspects.iter().any(|suspect| suspect.id == record.package) {
+            continue;
+        }
+        // the newest record wins, and after a revert the package is nobody's suspect
+        let state = record.resulting_state();
+        suspects.push(Suspect {
+            id: record.package.clone(),
+            state,
+            users: record.users,
+            mentioned: false,
+        });
+    }
+    suspects.retain(|suspect| suspect.state != State::Enabled);
+    suspects
+}
+
+/// Blames events on the removals that they name or, for crashes naming none, on all of them.
+/// Events with nobody to blame are left out.
+pub fn link(events: Vec<Event>, removals: &[Suspect]) -> Vec<Crash> {
+    events
+        .into_iter()
+        .filter_map(|event| {
+            let text = event.lines.join("\n");
+            let mentioned: Vec<&Suspect> = removals
+                .iter()
+                .filter(|removal| mentions(&text, &removal.id))
+                .collect();
+            let suspects: Vec<Suspect> = if !mentioned.is_empty() {
+                mentioned
+                    .into_iter()
+                    .map(|removal| Suspect {
+                        mentioned: true,
+                        id: removal.id.clone(),
+                        ..*removal
+                    })
+                    .collect()
+            } else if event.kind != Kind::MissingPackage {
+                removals
+                    .iter()
+                    .map(|removal| Suspect {
+                        id: removal.id.clone(),
+                        ..*removal
+                    })
+                    .collect()
+            } else {
+                vec![]
+            };
+            (!suspects.is_empty()).then_some(Crash { event, suspects })
+        })
+        .collect()
+}
+
+/// Follows logcat on the device, reading what it gained since the last look.
+pub struct Watch {
+    /// Time of the last line read in milliseconds since the unix epoch, by the device's clock.
+    since: u64,
+}
+
+impl Watch {
+    /// Starts watching from now on.
+    pub fn start<D: AndroidDevice + ?Sized>(device: &mut D) -> Result<Self, ShellRunError> {
+        let seconds = device
+            .shell_command_text("date +%s")?
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| ShellRunError::ParseError)?;
+        Ok(Watch {
+            since: seconds * 1000,
+        })
+    }
+
+    pub fn read<D: AndroidDevice + ?Sized>(
+        &mut self,
+        device: &mut D,
+    ) -> Result<Vec<Event>, ShellRunError> {
+        let output = device.shell_command_text(&format!(
+            "logcat -d -v threadtime -v epoch -T {}.{:03}",
+            self.since / 1000,
+            self.since % 1000
+        ))?;
+        // logcat repeats the line at the given time, and whatever else shares its millisecond
+        let new: Vec<&str> = output
+            .lines()
+            .filter(|line| parse_line(line).is_some_and(|line| line.time > self.since))
+            .collect();
+        if let Some(line) = new.last().and_then(|line| parse_line(line)) {
+            self.since = line.time;
+        }
+        Ok(parse(&new.join("\n")))
+    }
+}
+
+impl crate::App {
+    /// Lists crashes that look like the work of recent removals, with a way to revert those.
+    pub fn crash_panel(&mut self, ctx: &egui::Context) {
+        let Some(session) = self.session_mut() else {
+            return;
+        };
+        // suspects reverted since no longer count
+        for crash in session.crashes.iter_mut() {
+            crash.suspects.retain(|suspect| {
+                session
+                    .entries
+                    .get(&suspect.id)
+                    .is_none_or(|entry| entry.state != State::Enabled)
+            });
+        }
+        session.crashes.retain(|crash| !crash.suspects.is_empty());
+        if session.crashes.is_empty() {
+            return;
+        }
+
+        let mut revert = vec![];
+        let mut dismiss = None;
+        let mut clear = false;
+        TopBottomPanel::bottom("crash_panel")
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.add_space(4.0);
+                ui.horizontal(|ui| {
+                    ui.label(
+                        RichText::new(format!(
+                            "{} crashes since recent removals",
+                            session.crashes.len()
+                        ))
+                        .color(Color32::LIGHT_RED),
+                    );
+                    if ui.button("clear").clicked() {
+                        clear = true;
+                    }
+                });
+
+                ScrollArea::vertical().max_height(150.0).show(ui, |ui| {
+                    for (index, crash) in session.crashes.iter().enumerate().rev() {
+                        ui.horizontal_wrapped(|ui| {
+                            if ui.small_button("dismiss").clicked() {
+                                dismiss = Some(index);
+                            }
+                            ui.label(
+                                RichText::new(format!(
+                                    "{} {} in {}",
+                                    journal::age(crash.event.time / 1000),
+                                    crash.event.kind,
+                                    crash.event.process
+                                ))
+                                .size(12.0),
+                            );
+                            for suspect in crash.suspects.iter() {
+                                let button = if suspect.mentioned {
+                                    format!("revert {}", suspect.id)
+                                } else {
+                                    format!("revert {}?", suspect.id)
+                                };
+                                if ui
+                                    .small_button(button)
+                                    .on_hover_text(if suspect.mentioned {
+                                        "the crash names this package"
+                                    } else {
+                                        "removed shortly before the crash"
+                                    })
+                                    .clicked()
+                                {
+                                    revert.push(suspect.revert());
+                                }
+                            }
+                        });
+                        ui.label(
+                            RichText::new(crash.event.lines.join("\n"))
+                                .monospace()
+                                .size(10.0),
+                        );
+                    }
+                });
+                ui.add_space(4.0);
+            });
+
+        if clear {
+            session.crashes.clear();
+        } else if let Some(index) = dismiss {
+            session.crashes.remove(index);
+        }
+        self.queue_actions(revert);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_device::FakeDevice;
+
+    const LOGCAT: &str = "\
+  1735689599.100  1000  1000 I ActivityManager: Start proc 4321:com.example.gallery/u0a123
+  1735689599.600  4321  4321 E AndroidRuntime: FATAL EXCEPTION: main
+  1735689599.600  4321  4321 E AndroidRuntime: Process: com.example.gallery, PID: 4321
+  1735689599.600  4321  4321 E AndroidRuntime: java.lang.RuntimeException: Unable to start activity
+  1735689599.600  4321  4321 E AndroidRuntime: Caused by: android.content.pm.PackageManager$NameNotFoundException: com.example.cloud
+  1735689600.000  1000  1200 E ActivityManager: ANR in com.example.music (com.example.music/.Player)
+  1735689601.000  2000  2000 W Launcher: android.content.pm.PackageManager$NameNotFoundException: com.example.widgets
+";
+
+    fn removal(id: &str) -> Suspect {
+        Suspect {
+            id: id.to_string(),
+            state: State::Uninstalled,
+            users: UserScope::default(),
+            mentioned: false,
+        }
+    }
+
+    #[test]
+    fn parses_crashes_anrs_and_failed_lookups() {
+        let events = parse(LOGCAT);
+        let summary: Vec<(Kind, &str)> = events
+            .iter()
+            .map(|event| (event.kind, event.process.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Kind::Fatal, "com.example.gallery"),
+                (Kind::Anr, "com.example.music"),
+                (Kind::MissingPackage, "Launcher"),
+            ]
+        );
+        assert_eq!(events[0].lines.len(), 4);
+    }
+
+    #[test]
+    fn links_crashes_to_removals() {
+        let removals = [removal("com.example.cloud"), removal("com.example.widget")];
+        let crashes = link(parse(LOGCAT), &removals);
+
+        // the crash names the cloud package
+        assert_eq!(crashes[0].suspects.len(), 1);
+        assert_eq!(crashes[0].suspects[0].id, "com.example.cloud");
+        assert!(crashes[0].suspects[0].mentioned);
+        // the ANR names nothing removed, so everything removed is suspect
+        assert_eq!(crashes[1].suspects.len(), 2);
+        assert!(!crashes[1].suspects[0].mentioned);
+        // com.example.widgets is not com.example.widget
+        assert_eq!(crashes.len(), 2);
+    }
+
+    #[test]
+    fn reads_only_new_lines() {
+        let mut device = FakeDevice::new(34);
+        device.logcat = LOGCAT.to_string();
+        let mut watch = Watch::start(&mut device).unwrap();
+        // the log goes from New Year's Eve into the new year
+        assert_eq!(watch.read(&mut device).unwrap().len(), 3);
+        assert!(watch.read(&mut device).unwrap().is_empty());
+
+        device.logcat +=
+            "  1735689602.000  1000  1200 E ActivityManager: ANR in com.example.mail\n";
+        let events = watch.read(&mut device).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].time, 1735689602000);
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use crate::ShellRunError;
+use crate::adb_shell_text::AndroidDevice;
+
+/// What `dumpsys package` knows about a package, read when its entry is first expanded.
+#[derive(Clone, Default, Debug)]
+pub struct PackageDetails {
+    pub version_name: Option<String>,
+    pub version_code: Option<u64>,
+    pub installer: Option<String>,
+    pub partition: &'static str,
+    pub system: bool,
+    /// A system app with a newer version installed on top of it in /data.
+    pub updated_system_app: bool,
+    pub first_install_time: Option<String>,
+    pub last_update_time: Option<String>,
+    /// Size of the APKs on disk, in KiB.
+    pub size: Option<u64>,
+}
+
+/// The partition an APK was installed from, judging by its path.
+pub fn partition(path: &str) -> &'static str {
+    const PARTITIONS: [&str; 8] = [
+        "system_ext",
+        "system",
+        "product",
+        "vendor",
+        "odm",
+        "oem",
+        "apex",
+        "data",
+    ];
+    let top = path.trim_start_matches('/').split('/').next();
+    PARTITIONS
+        .into_iter()
+        .find(|&partition| top == Some(partition))
+        .unwrap_or("unknown")
+}
+
+/// What to measure for the size of a package: the APK directory, which holds the splits
+/// and extracted libraries too, or the APK alone when it sits in a directory shared with others.
+pub fn size_target(path: &str) -> &str {
+    const SHARED: [&str; 4] = ["app", "priv-app", "framework", "overlay"];
+    match path.rsplit_once('/') {
+        Some((dir, _))
+            if !SHARED
+                .iter()
+                .any(|shared| dir.ends_with(&format!("/{shared}"))) =>
+        {
+            dir
+        }
+        _ => path,
+    }
+}
+
+/// Sizes in KiB of many packages at once, given their IDs and APK paths.
+pub fn sizes<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+    packages: &[(&str, &str)],
+) -> Result<HashMap<String, u64>, ShellRunError> {
+    let mut by_target: HashMap<&str, Vec<&str>> = HashMap::new();
+    for &(id, path) in packages.iter() {
+        by_target.entry(size_target(path)).or_default().push(id);
+    }
+
+    let mut sizes = HashMap::new();
+    let targets: Vec<&str> = by_target.keys().copied().collect();
+    // keep each command line well below what the device shell accepts
+    for chunk in targets.chunks(64) {
+        let output = device.shell_command_text(&format!("du -sk {}", chunk.join(" ")))?;
+        for line in output.lines() {
+            let Some((size, target)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+            let (Ok(size), Some(ids)) = (size.parse(), by_target.get(target.trim())) else {
+                continue;
+            };
+            sizes.extend(ids.iter().map(|id| (id.to_string(), size)));
+        }
+    }
+    Ok(sizes)
+}
+
+fn value<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    line.split_whitespace()
+        .find_map(|field| field.strip_prefix(key)?.strip_prefix('='))
+}
+
+/// Parses the first `Package [id]` block of `dumpsys package <id>`, which
+/// describes the installed version rather than a hidden system copy.
+pub fn parse_dumpsys(text: &str, id: &str) -> PackageDetails {
+    let header = format!("Package [{id}]");
+    let mut details = PackageDetails::default();
+    let mut code_path = None;
+    for line in text
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with(&header))
+        .skip(1)
+        .take_while(|line| !line.trim_start().starts_with("Package ["))
+    {
+        let line = line.trim();
+        if let Some(version_code) = value(line, "versionCode") {
+            details.version_code = version_code.parse().ok();
+        } else if let Some(version_name) = line.strip_prefix("versionName=") {
+            details.version_name = Some(version_name.to_string());
+        } else if let Some(path) = line.strip_prefix("codePath=") {
+            code_path = Some(path.to_string());
+        } else if let Some(installer) = line.strip_prefix("installerPackageName=") {
+            details.installer = Some(installer.to_string()).filter(|i| i != "null");
+        } else if let Some(flags) = line
+            .strip_prefix("pkgFlags=")
+            .or_else(|| line.strip_prefix("flags="))
+        {
+            details.system = flags.contains(" SYSTEM ");
+            details.updated_system_app = flags.contains(" UPDATED_SYSTEM_APP ");
+        } else if let Some(time) = line.strip_prefix("firstInstallTime=") {
+            details.first_install_time = Some(time.to_string());
+        } else if let Some(time) = line.strip_prefix("lastUpdateTime=") {
+            details.last_update_time = Some(time.to_string());
+        }
+    }
+    details.partition = partition(code_path.as_deref().unwrap_or_default());
+    details
+}
+
+/// The version code of every package in `dumpsys package packages`, for devices whose `pm list
+/// packages` cannot print them, which came with Android 9.
+pub fn version_codes(text: &str) -> HashMap<String, i64> {
+    let mut codes = HashMap::new();
+    let mut id = None;
+    for line in text.lines().map(str::trim) {
+        if let Some(header) = line.strip_prefix("Package [") {
+            id = header.split_once(']').map(|(id, _)| id);
+        } else if let Some(id) = id
+            && let Some(code) = value(line, "versionCode").and_then(|code| code.parse().ok())
+        {
+            // the installed version comes first, hidden system copies after it
+            codes.entry(id.to_string()).or_insert(code);
+        }
+    }
+    codes
+}
+
+/// Reads the details of a package, with `path` being one of its APKs.
+pub fn read<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+    id: &str,
+    path: &str,
+) -> Result<PackageDetails, ShellRunError> {
+    let mut details = parse_dumpsys(
+        &device.shell_command_text(&format!("dumpsys package {id}"))?,
+        id,
+    );
+    if details.partition == "unknown" {
+        details.partition = partition(path);
+    }
+
+    details.size = device
+        .shell_command_text(&format!("du -sk {}", size_target(path)))?
+        .split_whitespace()
+        .next()
+        .and_then(|size| size.parse().ok());
+    Ok(details)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_device::FakeDevice;
+
+    const DUMPSYS: &str = "Activity Resolver Table:
+  Non-Data Actions:
+      android.intent.action.MAIN:
+        6d1a7d1 com.example.maps/.MainActivity filter 2f4b1f2
+
+Packages:
+  Package [com.example.maps] (a1b2c3d):
+    userId=10123
+    pkg=Package{5e6f7a8 com.example.maps}
+    codePath=/data/app/~~Qx1w==/com.example.maps-Zz9A==
+    resourcePath=/data/app/~~Qx1w==/com.example.maps-Zz9A==
+    versionCode=1100 minSdk=26 targetSdk=34
+    versionName=11.0.2
+    flags=[ SYSTEM HAS_CODE ALLOW_CLEAR_USER_DATA UPDATED_SYSTEM_APP ]
+    pkgFlags=[ SYSTEM HAS_CODE ALLOW_CLEAR_USER_DATA UPDATED_SYSTEM_APP ]
+    timeStamp=2024-05-02 10:11:12
+    firstInstallTime=2009-01-01 09:00:00
+    lastUpdateTime=2024-05-02 10:11:13
+    installerPackageName=com.android.vending
+
+Hidden system packages:
+  Package [com.example.maps] (f0e1d2c):
+    codePath=/product/app/Maps
+    versionCode=900 minSdk=26 targetSdk=33
+    versionName=9.0.0
+    pkgFlags=[ SYSTEM HAS_CODE ALLOW_CLEAR_USER_DATA ]
+";
+
+    #[test]
+    fn reads_the_installed_version_codes() {
+        let codes = version_codes(DUMPSYS);
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes["com.example.maps"], 1100);
+    }
+
+    #[test]
+    fn parses_the_installed_version() {
+        let details = parse_dumpsys(DUMPSYS, "com.example.maps");
+        assert_eq!(details.version_code, Some(1100));
+        assert_eq!(details.version_name.as_deref(), Some("11.0.2"));
+        assert_eq!(details.installer.as_deref(), Some("com.android.vending"));
+        assert_eq!(details.partition, "data");
+        assert!(details.system);
+        assert!(details.updated_system_app);
+        assert_eq!(
+            details.first_install_time.as_deref(),
+            Some("2009-01-01 09:00:00")
+        );
+        assert_eq!(
+            details.last_update_time.as_deref(),
+            Some("2024-05-02 10:11:13")
+        );
+    }
+
+    #[test]
+    fn partitions_come_from_the_path() {
+        assert_eq!(partition("/system/priv-app/Foo/Foo.apk"), "system");
+        assert_eq!(partition("/system_ext/app/Foo/Foo.apk"), "system_ext");
+        assert_eq!(partition("/product/app/Foo/Foo.apk"), "product");
+        assert_eq!(partition("/vendor/app/Foo/Foo.apk"), "vendor");
+        assert_eq!(partition("/data/app/foo/base.apk"), "data");
+        assert_eq!(partition("relative/foo.apk"), "unknown");
+    }
+
+    #[test]
+    fn sizes_measure_apk_directories_unless_shared() {
+        assert_eq!(
+            size_target("/data/app/~~Qx1w==/com.example-Zz9A==/base.apk"),
+            "/data/app/~~Qx1w==/com.example-Zz9A=="
+        );
+        assert_eq!(size_target("/system/app/Foo/Foo.apk"), "/system/app/Foo");
+        assert_eq!(
+            size_target("/system/framework/framework-res.apk"),
+            "/system/framework/framework-res.apk"
+        );
+        assert_eq!(
+            size_target("/product/overlay/Foo.apk"),
+            "/product/overlay/Foo.apk"
+        );
+    }
+
+    #[test]
+    fn reads_sizes_of_many_packages_at_once() {
+        let mut device = FakeDevice::new(34)
+            .with_package("com.example.a", "A")
+            .with_package("com.example.b", "B");
+        let sizes = sizes(
+            &mut device,
+            &[
+                ("com.example.a", "/data/app/com.example.a/base.apk"),
+                ("com.example.b", "/data/app/com.example.b/base.apk"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(sizes.len(), 2);
+        assert_eq!(device.commands.len(), 1);
+    }
+
+    #[test]
+    fn reads_details_from_the_device() {
+        let mut device = FakeDevice::new(34).with_package("com.example.bloat", "Bloat");
+        let details = read(
+            &mut device,
+            "com.example.bloat",
+            "/data/app/com.example.bloat/base.apk",
+        )
+        .unwrap();
+        assert_eq!(details.version_name.as_deref(), Some("1.0"));
+        assert_eq!(details.partition, "data");
+        assert!(!details.updated_system_app);
+        assert!(details.size.is_some());
+    }
+}
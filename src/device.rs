@@ -0,0 +1,382 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Display;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread::{JoinHandle, sleep, spawn};
+use std::time::{Duration, Instant};
+
+use adb_client::{ADBDeviceExt, ADBTcpDevice, ADBUSBDevice, USBTransport, is_adb_device};
+use rusb::{Device, GlobalContext};
+
+use crate::adb_shell_text::AndroidDevice;
+use crate::details::PackageDetails;
+use crate::users::{AndroidUser, UserScope};
+use crate::{
+    Action, FrontendPayload, PackageIdentifier, ShellRunError, WORKER_THREAD_POLL, WorkerExit,
+    action, crash_watch, health, icons, listview, protected, reconcile, worker_thread,
+};
+
+/// Identifies a USB device by its position on the bus while it stays plugged in.
+type UsbLocation = (u8, u8);
+/// How long a lost network device gets to come back, like after a reboot, before giving up.
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Clone)]
+pub enum Transport {
+    Usb { vendor_id: u16, product_id: u16 },
+    Tcp(SocketAddr),
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::Usb {
+                vendor_id,
+                product_id,
+            } => write!(f, "{vendor_id:04x}:{product_id:04x}"),
+            Transport::Tcp(address) => write!(f, "{address}"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DeviceInfo {
+    pub serial: String,
+    pub model: String,
+    pub transport: Transport,
+}
+
+impl DeviceInfo {
+    pub fn read<D: AndroidDevice + ?Sized>(
+        device: &mut D,
+        serial: String,
+        transport: Transport,
+    ) -> Result<Self, ShellRunError> {
+        let model = device
+            .shell_command_text("getprop ro.product.model")?
+            .trim()
+            .to_string();
+        Ok(DeviceInfo {
+            serial,
+            model,
+            transport,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        format!("{} ({}) {}", self.model, self.serial, self.transport)
+    }
+}
+
+/// What package listing and actions need to know about a device.
+pub struct DeviceProps {
+    pub serial: String,
+    pub sdk_version: u16,
+    pub users: Vec<AndroidUser>,
+    /// Whether backups also get copied to this computer before uninstalling.
+    pub pull_backups: bool,
+    /// Packages actions refuse to uninstall or disable.
+    pub protected: BTreeMap<PackageIdentifier, protected::Role>,
+    /// Lets actions go ahead with protected packages anyway.
+    pub allow_protected: bool,
+}
+
+/// The frontend's view of a connected device with its own package list and action queue.
+pub struct Session {
+    pub info: DeviceInfo,
+    pub users: Vec<AndroidUser>,
+    pub user_scope: UserScope,
+    pub user_scope_tx: Sender<UserScope>,
+    pub sdk_version: u16,
+    pub pull_backups: bool,
+    pub pull_backups_tx: Sender<bool>,
+    pub protected: BTreeMap<PackageIdentifier, protected::Role>,
+    pub entries: BTreeMap<String, listview::Entry>,
+
+    pub package_diff_rx: Receiver<FrontendPayload>,
+    pub device_lost_rx: Receiver<()>,
+    pub action_tx: Sender<action::Queued>,
+    pub action_error_rx: Receiver<action::Failure>,
+    pub action_done_rx: Receiver<()>,
+    /// Asks the worker for the details of a package, given its ID and APK path.
+    pub details_tx: Sender<(String, String)>,
+    pub details_rx: Receiver<(String, Result<PackageDetails, String>)>,
+    /// Asks the worker to reboot the device and verify it comes back healthy.
+    pub reboot_tx: Sender<()>,
+    pub health_rx: Receiver<health::Report>,
+    /// Users and protected packages read again once a reboot finished.
+    pub booted_rx: Receiver<(
+        Vec<AndroidUser>,
+        BTreeMap<PackageIdentifier, protected::Role>,
+    )>,
+    pub health: Option<health::Report>,
+    pub crash_rx: Receiver<Vec<crash_watch::Crash>>,
+    /// Crashes that may be down to recent removals, newest last.
+    pub crashes: Vec<crash_watch::Crash>,
+    pub icon_rx: Receiver<Vec<(PackageIdentifier, icons::Icon)>>,
+    pub icons: BTreeMap<PackageIdentifier, icons::Icon>,
+    /// Problems the worker ran into outside of actions, for the error panel.
+    pub notice_rx: Receiver<String>,
+
+    pub failures: Vec<action::Failure>,
+    /// Whether the journal holds anything done to the device, which reboot and verify needs.
+    pub journaled: bool,
+    pub have_packages: bool,
+    pub busy: bool,
+}
+
+impl Session {
+    /// Takes in what the worker sent since the last frame.
+    /// Returns false once the device is gone.
+    pub fn poll(&mut self) -> bool {
+        if let Ok(()) = self.action_done_rx.try_recv() {
+            self.busy = false;
+        }
+
+        while let Ok(failure) = self.action_error_rx.try_recv() {
+            log::error!("{}", failure.error);
+            self.failures.push(failure);
+        }
+
+        while let Ok(report) = self.health_rx.try_recv() {
+            self.health = Some(report);
+        }
+
+        while let Ok((users, protected)) = self.booted_rx.try_recv() {
+            self.users = users;
+            self.protected = protected;
+        }
+
+        while let Ok(icons) = self.icon_rx.try_recv() {
+            self.icons.extend(icons);
+        }
+
+        while let Ok(crashes) = self.crash_rx.try_recv() {
+            self.crashes.extend(crashes);
+            let excess = self.crashes.len().saturating_sub(crash_watch::KEPT);
+            self.crashes.drain(..excess);
+        }
+
+        while let Ok((id, details)) = self.details_rx.try_recv() {
+            if let Some(entry) = self.entries.get_mut(&id) {
+                entry.details = Some(details);
+            }
+        }
+
+        if let Ok(package_diff) = self.package_diff_rx.try_recv()
+            && package_diff.users == self.user_scope
+        {
+            self.have_packages = true;
+            reconcile(&mut self.entries, package_diff);
+        }
+
+        if let Ok(()) = self.device_lost_rx.try_recv() {
+            log::warn!("device {} lost", self.info.serial);
+            return false;
+        }
+        true
+    }
+
+    /// What actions would see of the device, for dry runs.
+    pub fn props(&self) -> DeviceProps {
+        DeviceProps {
+            serial: self.info.serial.clone(),
+            sdk_version: self.sdk_version,
+            users: self.users.clone(),
+            pull_backups: self.pull_backups,
+            protected: self.protected.clone(),
+            allow_protected: false,
+        }
+    }
+
+    /// Hands actions to the worker, which applies them before it next lists packages.
+    pub fn send_actions(&mut self, actions: Vec<Action>, allow_protected: bool) {
+        if actions.is_empty() {
+            return;
+        }
+        self.action_tx
+            .send(action::Queued {
+                actions,
+                allow_protected,
+            })
+            .expect("failed to send message to backend");
+        self.busy = true;
+        // the worker records every action in the journal, even those that fail
+        self.journaled = true;
+    }
+
+    pub fn user_name(&self, user_scope: UserScope) -> String {
+        match user_scope {
+            UserScope::User(id) => match self.users.iter().find(|user| user.id == id) {
+                Some(user) => format!("{} ({id})", user.name),
+                None => user_scope.to_string(),
+            },
+            UserScope::All => "All users".to_string(),
+        }
+    }
+
+    /// Switches the package list and future actions over to other users.
+    pub fn set_user_scope(&mut self, user_scope: UserScope) {
+        if user_scope == self.user_scope {
+            return;
+        }
+        self.user_scope = user_scope;
+        self.entries.clear();
+        self.have_packages = false;
+        let _ = self.user_scope_tx.send(user_scope);
+    }
+}
+
+/// Lists the attached USB devices that expose an ADB interface.
+fn adb_usb_devices() -> Vec<Device<GlobalContext>> {
+    let Ok(devices) = rusb::devices() else {
+        return vec![];
+    };
+    devices
+        .iter()
+        .filter(|device| {
+            device
+                .device_descriptor()
+                .is_ok_and(|des| is_adb_device(device, &des))
+        })
+        .collect()
+}
+
+fn connect_usb(usb_device: Device<GlobalContext>) -> Option<(Box<dyn ADBDeviceExt>, Transport)> {
+    let descriptor = usb_device.device_descriptor().ok()?;
+    let transport = Transport::Usb {
+        vendor_id: descriptor.vendor_id(),
+        product_id: descriptor.product_id(),
+    };
+    match ADBUSBDevice::new_from_transport(USBTransport::new_from_device(usb_device), None) {
+        Ok(device) => Some((device.boxed(), transport)),
+        Err(e) => {
+            log::warn!("failed to connect to the device: {e}");
+            None
+        }
+    }
+}
+
+/// The serial number a USB device reports, which Android sets to `ro.serialno`.
+fn usb_serial(usb_device: &Device<GlobalContext>) -> Option<String> {
+    let descriptor = usb_device.device_descriptor().ok()?;
+    usb_device
+        .open()
+        .ok()?
+        .read_serial_number_string_ascii(&descriptor)
+        .ok()
+}
+
+/// Connects to the USB device with the given serial number or, without one, to the only
+/// USB device attached.
+pub fn connect_usb_serial(serial: Option<&str>) -> Result<Box<dyn ADBDeviceExt>, String> {
+    let mut devices = adb_usb_devices();
+    if let Some(serial) = serial {
+        devices.retain(|device| usb_serial(device).as_deref() == Some(serial));
+    }
+    let usb_device = match devices.len() {
+        0 => return Err("no USB device with USB debugging enabled".to_string()),
+        1 => devices.remove(0),
+        _ => {
+            let serials: Vec<String> = devices.iter().filter_map(usb_serial).collect();
+            return Err(format!(
+                "several devices attached, pick one with --serial: {}",
+                serials.join(", ")
+            ));
+        }
+    };
+    connect_usb(usb_device)
+        .map(|(device, _)| device)
+        .ok_or_else(|| "failed to connect to the device".to_string())
+}
+
+/// Connects to a device in wireless debugging or `adb tcpip` mode.
+pub fn connect_tcp(address: &str) -> Result<(Box<dyn ADBDeviceExt>, Transport), String> {
+    let address = address
+        .to_socket_addrs()
+        .map_err(|e| format!("invalid address {address}: {e}"))?
+        .next()
+        .ok_or_else(|| format!("{address} does not resolve to any address"))?;
+    let device = ADBTcpDevice::new(address).map_err(|e| e.to_string())?;
+    Ok((device.boxed(), Transport::Tcp(address)))
+}
+
+/// Runs workers for a network device until the frontend drops its session, connecting
+/// again whenever the device goes away, as USB devices get rediscovered when replugged.
+/// Gives up once the device has not been reachable for [`RECONNECT_TIMEOUT`].
+pub fn tcp_thread(
+    address: String,
+    mut device: Box<dyn ADBDeviceExt>,
+    mut transport: Transport,
+    session_tx: Sender<Session>,
+    ctx: egui::Context,
+) {
+    let mut deadline = Instant::now() + RECONNECT_TIMEOUT;
+    loop {
+        match worker_thread(device, transport, session_tx.clone(), ctx.clone()) {
+            WorkerExit::SessionDropped => return,
+            WorkerExit::DeviceLost => deadline = Instant::now() + RECONNECT_TIMEOUT,
+            WorkerExit::Unprepared => {}
+        }
+        (device, transport) = loop {
+            if Instant::now() > deadline {
+                log::warn!("gave up reconnecting to {address}");
+                return;
+            }
+            sleep(WORKER_THREAD_POLL);
+            match connect_tcp(&address) {
+                Ok(connection) => break connection,
+                Err(e) => log::warn!("failed to reconnect to {address}: {e}"),
+            }
+        };
+    }
+}
+
+/// Spawns a worker for every ADB device that gets plugged in. Workers announce
+/// themselves to the frontend with a [`Session`] once the device is usable.
+pub fn discovery_thread(session_tx: Sender<Session>, ctx: egui::Context) {
+    let mut workers: HashMap<UsbLocation, JoinHandle<Option<WorkerExit>>> = HashMap::new();
+    // devices whose session the frontend turned down, left alone until they are unplugged
+    let mut refused: HashSet<UsbLocation> = HashSet::new();
+    loop {
+        // a worker exits when its device goes away or fails to connect,
+        // so a device still attached after that gets another attempt
+        let finished: Vec<UsbLocation> = workers
+            .iter()
+            .filter(|(_, worker)| worker.is_finished())
+            .map(|(location, _)| *location)
+            .collect();
+        for location in finished {
+            if let Some(worker) = workers.remove(&location)
+                && let Ok(Some(WorkerExit::SessionDropped)) = worker.join()
+            {
+                refused.insert(location);
+            }
+        }
+
+        let attached = adb_usb_devices();
+        refused.retain(|location| {
+            attached
+                .iter()
+                .any(|device| (device.bus_number(), device.address()) == *location)
+        });
+        for usb_device in attached {
+            let location = (usb_device.bus_number(), usb_device.address());
+            if workers.contains_key(&location) || refused.contains(&location) {
+                continue;
+            }
+
+            let session_tx = session_tx.clone();
+            let ctx = ctx.clone();
+            workers.insert(
+                location,
+                spawn(move || {
+                    let (device, transport) = connect_usb(usb_device)?;
+                    Some(worker_thread(device, transport, session_tx, ctx))
+                }),
+            );
+        }
+
+        sleep(WORKER_THREAD_POLL);
+    }
+}
@@ -0,0 +1,72 @@
+use egui::{Id, Modal, RichText, ScrollArea};
+
+use crate::Action;
+use crate::device::DeviceProps;
+
+/// The commands a list of actions would run, each preceded by a comment naming the action.
+pub fn plan(actions: &[Action], props: &DeviceProps) -> Vec<String> {
+    let mut lines = vec![];
+    for action in actions.iter() {
+        lines.push(format!("# {action}"));
+        lines.extend(action.planned_commands(props));
+    }
+    lines
+}
+
+impl crate::App {
+    /// Sends actions to the current device, or in dry run mode only shows what they would run.
+    pub fn queue_actions(&mut self, actions: Vec<Action>) {
+        self.queue_actions_allowing(actions, false);
+    }
+
+    /// Like `queue_actions`, but can let actions through against protected packages.
+    pub fn queue_actions_allowing(&mut self, actions: Vec<Action>, allow_protected: bool) {
+        if actions.is_empty() {
+            return;
+        }
+        let dry_run = self.dry_run;
+        let Some(session) = self.session_mut() else {
+            return;
+        };
+        if dry_run {
+            let props = DeviceProps {
+                allow_protected,
+                ..session.props()
+            };
+            self.dry_run_plan = Some(plan(&actions, &props));
+        } else {
+            session.send_actions(actions, allow_protected);
+        }
+    }
+
+    pub fn dry_run_preview(&mut self, ctx: &egui::Context) {
+        let Some(lines) = self.dry_run_plan.as_ref() else {
+            return;
+        };
+
+        let mut close = false;
+        let modal = Modal::new(Id::new("dry_run_preview")).show(ctx, |ui| {
+            ui.heading("Dry run");
+            ui.label("Nothing was changed. These are the commands zilch would run on the device.");
+            ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                for line in lines.iter() {
+                    ui.label(RichText::new(line).monospace().size(10.0));
+                }
+            });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("copy").clicked() {
+                    ui.ctx().copy_text(lines.join("\n"));
+                }
+                if ui.button("close").clicked() {
+                    close = true;
+                }
+            });
+        });
+
+        if close || modal.should_close() {
+            self.dry_run_plan = None;
+        }
+    }
+}
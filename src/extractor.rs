@@ -0,0 +1,165 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+use crate::{PackageIdentifier, ShellRunError};
+
+/// The version of the output of extractor/Main.java that zilch reads.
+pub const PROTOCOL: u32 = 1;
+
+#[derive(Deserialize)]
+struct Header {
+    protocol: u32,
+}
+
+/// What the extractor prints about each installed app. It prints more than zilch reads.
+#[derive(Deserialize)]
+pub struct App {
+    pub id: PackageIdentifier,
+    pub label: String,
+}
+
+/// PNG images by package.
+pub type Pngs = Vec<(PackageIdentifier, Vec<u8>)>;
+
+#[derive(Deserialize)]
+struct Icon {
+    id: PackageIdentifier,
+    /// A base64 PNG.
+    icon: String,
+}
+
+/// The records after the protocol header. Output without the header is refused, as is a
+/// header of another protocol. Records that fail to parse are logged and skipped, as one odd
+/// app should not take the others down with it.
+fn records<T: DeserializeOwned>(text: &str) -> Result<Vec<T>, ShellRunError> {
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let first = lines.next().unwrap_or_default();
+    let header: Header = serde_json::from_str(first).map_err(|e| {
+        log::error!("failed to parse the extractor header {first:?}: {e}");
+        ShellRunError::ParseError
+    })?;
+    if header.protocol != PROTOCOL {
+        log::error!(
+            "the extractor speaks protocol {}, zilch only {PROTOCOL}",
+            header.protocol
+        );
+        return Err(ShellRunError::ParseError);
+    }
+    let records = lines
+        .filter_map(|line| {
+            serde_json::from_str(line)
+                .inspect_err(|e| log::error!("skipped extractor output {line:?}: {e}"))
+                .ok()
+        })
+        .collect();
+    Ok(records)
+}
+
+/// Apps with their labels, from what the extractor printed when run without arguments.
+pub fn apps(text: &str) -> Result<Vec<App>, ShellRunError> {
+    records(text)
+}
+
+/// PNG icons by package, from what the extractor printed when asked for icons.
+pub fn icons(text: &str) -> Result<Pngs, ShellRunError> {
+    let pngs = records::<Icon>(text)?
+        .into_iter()
+        .filter_map(|Icon { id, icon }| match STANDARD.decode(&icon) {
+            Ok(png) => Some((id, png)),
+            Err(e) => {
+                log::error!("skipped the icon of {id}: {e}");
+                None
+            }
+        })
+        .collect();
+    Ok(pngs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(apps: &[App]) -> Vec<(&str, &str)> {
+        apps.iter()
+            .map(|app| (app.id.as_str(), app.label.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn reads_apps_of_the_current_protocol() {
+        let text = "{\"protocol\":1}\n\
+                    {\"id\":\"com.example.a\",\"label\":\"Two\\nlines\",\"uid\":10123,\
+                    \"version\":4,\"flags\":0,\"enabled\":true}\n";
+        let apps = apps(text).unwrap();
+        assert_eq!(ids(&apps), [("com.example.a", "Two\nlines")]);
+    }
+
+    #[test]
+    fn reads_apps_as_the_extractor_prints_them() {
+        // org.json escapes slashes
+        let text = "{\"protocol\":1}\n\
+                    {\"id\":\"com.example.ac\",\"label\":\"AC\\/DC\",\"uid\":10124,\
+                    \"version\":2,\"flags\":8388608,\"enabled\":false}\n";
+        assert_eq!(ids(&apps(text).unwrap()), [("com.example.ac", "AC/DC")]);
+    }
+
+    #[test]
+    fn refuses_other_protocols() {
+        assert!(matches!(
+            apps("{\"protocol\":2}\n"),
+            Err(ShellRunError::ParseError)
+        ));
+    }
+
+    #[test]
+    fn skips_broken_lines() {
+        let text = "{\"protocol\":1}\n{\"id\":\"com.example.a\"}\n\
+                    {\"id\":\"com.example.b\",\"label\":\"B\"}\n";
+        assert_eq!(ids(&apps(text).unwrap()), [("com.example.b", "B")]);
+    }
+
+    #[test]
+    fn refuses_output_without_a_header() {
+        for text in ["10123 com.example.a Label\n", ""] {
+            assert!(matches!(apps(text), Err(ShellRunError::ParseError)));
+            assert!(matches!(icons(text), Err(ShellRunError::ParseError)));
+        }
+    }
+
+    #[test]
+    fn decodes_icons() {
+        let text = "{\"protocol\":1}\n{\"id\":\"com.example.a\",\"icon\":\"UE5HIQ==\"}\n";
+        assert_eq!(
+            icons(text).unwrap(),
+            [("com.example.a".to_string(), b"PNG!".to_vec())]
+        );
+        let broken = icons("{\"protocol\":1}\n{\"id\":\"a\",\"icon\":\"!\"}\n");
+        assert!(broken.unwrap().is_empty());
+    }
+
+    #[test]
+    fn main_java_speaks_the_same_protocol() {
+        let source = include_str!("../extractor/Main.java");
+        assert!(source.contains(&format!("PROTOCOL = {PROTOCOL};")));
+    }
+
+    /// Looks for strings only an extractor printing JSON records has in its string pool.
+    #[test]
+    #[ignore = "src/extractor.dex predates the protocol, run once it is rebuilt with extractor/build.sh"]
+    fn shipped_extractor_speaks_the_protocol() {
+        // dex strings are stored as their length followed by the bytes and a NUL
+        let has_string = |s: &str| {
+            let mut stored = vec![s.len() as u8];
+            stored.extend_from_slice(s.as_bytes());
+            stored.push(0);
+            crate::LABEL_EXTRACTOR
+                .windows(stored.len())
+                .any(|window| window == stored)
+        };
+        for s in ["protocol", "icons", "version", "Lorg/json/JSONObject;"] {
+            assert!(has_string(s), "the extractor has no {s:?} string");
+        }
+    }
+}
@@ -0,0 +1,586 @@
+//! A scripted stand-in for an Android device, which understands the shell commands
+//! zilch runs and answers them like `pm` would.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::json;
+
+use crate::adb_shell_text::AndroidDevice;
+use crate::protected::Role;
+use crate::{ShellRunError, crash_watch, extractor};
+
+const TMP: &str = "/data/local/tmp/";
+
+pub struct FakePackage {
+    pub path: String,
+    pub label: String,
+    /// Users the package is installed for.
+    pub installed: BTreeSet<u32>,
+    /// Users that have the package disabled.
+    pub disabled: BTreeSet<u32>,
+}
+
+/// What a scripted command does instead of what the fake would normally do.
+pub enum Reply {
+    Print(String),
+    Timeout,
+}
+
+struct InstallSession {
+    user: u32,
+    apks: Vec<String>,
+}
+
+pub struct FakeDevice {
+    pub sdk_version: u16,
+    pub serial: String,
+    pub users: Vec<(u32, String)>,
+    pub packages: BTreeMap<String, FakePackage>,
+    /// Files on the device, keyed by path.
+    pub files: BTreeMap<String, Vec<u8>>,
+    /// Every shell command run so far.
+    pub commands: Vec<String>,
+    /// Packages acting as the launcher, keyboard, dialer or SMS app.
+    pub roles: Vec<(Role, String)>,
+    /// Processes `pidof` finds.
+    pub running: BTreeSet<String>,
+    pub boot_completed: bool,
+    pub reboots: u32,
+    /// What `logcat -v threadtime -v epoch` prints.
+    pub logcat: String,
+    /// Counts package changes like the sequence number of `dumpsys package changes`.
+    pub package_changes: u32,
+    scripted: Vec<(String, Reply)>,
+    install_sessions: BTreeMap<u32, InstallSession>,
+}
+
+impl FakeDevice {
+    pub fn new(sdk_version: u16) -> Self {
+        FakeDevice {
+            sdk_version,
+            serial: "FAKE0001".to_string(),
+            users: vec![(0, "Owner".to_string())],
+            packages: BTreeMap::new(),
+            files: BTreeMap::new(),
+            commands: vec![],
+            roles: vec![],
+            running: ["com.android.systemui", "com.android.phone"]
+                .map(String::from)
+                .into(),
+            boot_completed: true,
+            reboots: 0,
+            logcat: String::default(),
+            package_changes: 0,
+            scripted: vec![],
+            install_sessions: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_role(mut self, role: Role, id: &str) -> Self {
+        self.roles.push((role, id.to_string()));
+        self
+    }
+
+    fn role(&self, role: Role) -> Option<&str> {
+        self.roles
+            .iter()
+            .find(|(filled, _)| *filled == role)
+            .map(|(_, id)| id.as_str())
+    }
+
+    pub fn with_user(mut self, id: u32, name: &str) -> Self {
+        self.users.push((id, name.to_string()));
+        self
+    }
+
+    /// Adds a package installed for every user, with its base APK in `/data/app`.
+    pub fn with_package(mut self, id: &str, label: &str) -> Self {
+        let path = format!("/data/app/{id}/base.apk");
+        self.files
+            .insert(path.clone(), format!("base of {id}").into_bytes());
+        self.packages.insert(
+            id.to_string(),
+            FakePackage {
+                path,
+                label: label.to_string(),
+                installed: self.users.iter().map(|(user, _)| *user).collect(),
+                disabled: BTreeSet::new(),
+            },
+        );
+        self
+    }
+
+    /// Leaves a package installed for a single user only.
+    pub fn only_for(mut self, id: &str, user: u32) -> Self {
+        if let Some(package) = self.packages.get_mut(id) {
+            package.installed = BTreeSet::from([user]);
+        }
+        self
+    }
+
+    /// Gives a package a split APK next to its base APK.
+    pub fn with_split(mut self, id: &str, split: &str) -> Self {
+        self.files.insert(
+            format!("/data/app/{id}/split_{split}.apk"),
+            format!("{split} split of {id}").into_bytes(),
+        );
+        self
+    }
+
+    /// Makes commands starting with `prefix` reply this way from now on.
+    pub fn script(&mut self, prefix: &str, reply: Reply) {
+        self.scripted.push((prefix.to_string(), reply));
+    }
+
+    /// Has commands starting with `prefix` reply as usual again.
+    pub fn unscript(&mut self, prefix: &str) {
+        self.scripted.retain(|(scripted, _)| scripted != prefix);
+    }
+
+    /// Drops every trace of a package except backups, like a factory reset of it would.
+    pub fn forget(&mut self, id: &str) {
+        self.packages.remove(id);
+        let dir = format!("/data/app/{id}/");
+        self.files.retain(|path, _| !path.starts_with(&dir));
+    }
+
+    pub fn package(&self, id: &str) -> &FakePackage {
+        &self.packages[id]
+    }
+
+    fn files_in(&self, dir: &str) -> Vec<String> {
+        let dir = format!("{}/", dir.trim_end_matches('/'));
+        self.files
+            .keys()
+            .filter(|path| {
+                path.strip_prefix(&dir)
+                    .is_some_and(|rest| !rest.contains('/'))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Backups live in `/data/local/tmp/<id>/` or, for old ones, `/data/local/tmp/<id>.apk`.
+    fn install_from(&mut self, user: u32, apk: &str) -> String {
+        let Some(name) = apk.strip_prefix(TMP) else {
+            return format!("Error: unable to open file: {apk}");
+        };
+        if !self.files.contains_key(apk) {
+            return format!("Error: unable to open file: {apk}");
+        }
+        let id = match name.split_once('/') {
+            Some((id, _)) => id,
+            None => name.trim_end_matches(".apk"),
+        };
+
+        let package = self.packages.entry(id.to_string()).or_insert(FakePackage {
+            path: format!("/data/app/{id}/base.apk"),
+            label: id.to_string(),
+            installed: BTreeSet::new(),
+            disabled: BTreeSet::new(),
+        });
+        package.installed.insert(user);
+        "Success".to_string()
+    }
+
+    fn run(&mut self, command: &str) -> String {
+        if let Some(tail) = command.strip_prefix("CLASSPATH=") {
+            return self.extract_labels(tail);
+        }
+        if command.contains(" && ") {
+            // the chained commands are silent unless they fail, so output stands in
+            // for the exit status the shell would stop at
+            for part in command.split(" && ") {
+                let output = self.run(part);
+                if !output.is_empty() {
+                    return output;
+                }
+            }
+            return String::default();
+        }
+        if command == "date +%s" {
+            return "1735689590\n".to_string();
+        }
+        if let Some((_, since)) = command
+            .strip_prefix("logcat ")
+            .and_then(|c| c.rsplit_once("-T "))
+        {
+            let since = crash_watch::epoch_millis(since).unwrap_or_default();
+            return self
+                .logcat
+                .lines()
+                .filter(|line| {
+                    let time = line.split_whitespace().next().unwrap_or_default();
+                    crash_watch::epoch_millis(time).is_some_and(|time| time >= since)
+                })
+                .map(|line| format!("{line}\n"))
+                .collect();
+        }
+
+        let words: Vec<&str> = command.split_whitespace().collect();
+        if let [
+            "pm",
+            "uninstall" | "disable-user" | "enable" | "install-existing" | "install"
+            | "install-commit",
+            ..,
+        ] = words.as_slice()
+        {
+            self.package_changes += 1;
+        }
+        match words.as_slice() {
+            ["getprop", "ro.build.version.sdk"] => format!("{}\n", self.sdk_version),
+            ["getprop", "ro.serialno"] => format!("{}\n", self.serial),
+            ["getprop", "ro.product.model"] => "Fake Phone\n".to_string(),
+            ["getprop", "sys.boot_completed"] if self.boot_completed => "1\n".to_string(),
+            ["getprop", "sys.boot_completed"] => "\n".to_string(),
+            ["pidof", name] if self.running.contains(*name) => "1234\n".to_string(),
+            ["pidof", _] => String::default(),
+            ["pm", "list", "users"] => {
+                let mut output = "Users:\n".to_string();
+                for (id, name) in self.users.iter() {
+                    output += &format!("\tUserInfo{{{id}:{name}:c13}} running\n");
+                }
+                output
+            }
+            ["pm", "list", "packages", "-f", options @ .., "--user", user] => {
+                let user = user.parse().unwrap_or_default();
+                // every fake package is at version 1, as dumpsys says
+                let version = match options {
+                    ["--show-versioncode"] if self.sdk_version >= 28 => " versionCode:1",
+                    [] => "",
+                    _ => return format!("Error: Unknown option: {}\n", options.join(" ")),
+                };
+                self.packages
+                    .iter()
+                    .filter(|(_, package)| package.installed.contains(&user))
+                    .map(|(id, package)| format!("package:{}={id}{version}\n", package.path))
+                    .collect()
+            }
+            ["pm", "list", "packages", "--user", user] => {
+                let user = user.parse().unwrap_or_default();
+                self.packages
+                    .iter()
+                    .filter(|(_, package)| package.installed.contains(&user))
+                    .map(|(id, _)| format!("package:{id}\n"))
+                    .collect()
+            }
+            ["pm", "list", "packages", "-e", "--user", user] => {
+                let user = user.parse().unwrap_or_default();
+                self.packages
+                    .iter()
+                    .filter(|(_, package)| {
+                        package.installed.contains(&user) && !package.disabled.contains(&user)
+                    })
+                    .map(|(id, _)| format!("package:{id}\n"))
+                    .collect()
+            }
+            ["pm", "list", "packages", "-d", "--user", user] => {
+                let user = user.parse().unwrap_or_default();
+                self.packages
+                    .iter()
+                    .filter(|(_, package)| {
+                        package.installed.contains(&user) && package.disabled.contains(&user)
+                    })
+                    .map(|(id, _)| format!("package:{id}\n"))
+                    .collect()
+            }
+            ["pm", "path", "--user", user, id] => {
+                let user = user.parse().unwrap_or_default();
+                match self.packages.get(*id) {
+                    Some(package) if package.installed.contains(&user) => {
+                        let dir = package.path.rsplit_once('/').map(|(dir, _)| dir);
+                        self.files_in(dir.unwrap_or_default())
+                            .into_iter()
+                            .map(|path| format!("package:{path}\n"))
+                            .collect()
+                    }
+                    _ => String::default(),
+                }
+            }
+            ["pm", "uninstall", "--user", user, "-k", id] => {
+                let user = user.parse().unwrap_or_default();
+                let removed = self
+                    .packages
+                    .get_mut(*id)
+                    .is_some_and(|package| package.installed.remove(&user));
+                if removed {
+                    "Success\n".to_string()
+                } else {
+                    "Failure [DELETE_FAILED_INTERNAL_ERROR]\n".to_string()
+                }
+            }
+            ["pm", "disable-user", "--user", user, id] => {
+                let user = user.parse().unwrap_or_default();
+                match self.packages.get_mut(*id) {
+                    Some(package) if package.installed.contains(&user) => {
+                        package.disabled.insert(user);
+                        format!("Package {id} new state: disabled-user\n")
+                    }
+                    _ => format!("Error: Unknown package: {id}\n"),
+                }
+            }
+            ["pm", "enable", "--user", user, id] => {
+                let user = user.parse().unwrap_or_default();
+                match self.packages.get_mut(*id) {
+                    Some(package) if package.installed.contains(&user) => {
+                        package.disabled.remove(&user);
+                        format!("Package {id} new state: enabled\n")
+                    }
+                    _ => format!("Error: Unknown package: {id}\n"),
+                }
+            }
+            // like pm, hands the package to any user, whether they had it before or not
+            ["pm", "install-existing", "--user", user, id] => {
+                let user = user.parse().unwrap_or_default();
+                match self.packages.get_mut(*id) {
+                    Some(package) => {
+                        package.installed.insert(user);
+                        format!("Package {id} installed for user: {user}\n")
+                    }
+                    None => format!("Error: Package {id} is inaccessible or not found\n"),
+                }
+            }
+            ["pm", "block" | "unblock", "--user", _, id] => {
+                format!("Package {id} new blocked state: {}\n", words[1] == "block")
+            }
+            ["pm", "install", "-r", "--user", user, apk] => {
+                self.install_from(user.parse().unwrap_or_default(), apk)
+            }
+            ["pm", "install-create", "-r", "--user", user, "-S", _] => {
+                let session = self.install_sessions.len() as u32 + 1000;
+                self.install_sessions.insert(
+                    session,
+                    InstallSession {
+                        user: user.parse().unwrap_or_default(),
+                        apks: vec![],
+                    },
+                );
+                format!("Success: created install session [{session}]\n")
+            }
+            ["pm", "install-write", "-S", size, session, _, apk] => {
+                let Some(session) = session
+                    .parse()
+                    .ok()
+                    .and_then(|session: u32| self.install_sessions.get_mut(&session))
+                else {
+                    return "Error: invalid session\n".to_string();
+                };
+                session.apks.push(apk.to_string());
+                format!("Success: streamed {size} bytes\n")
+            }
+            ["pm", "install-commit", session] => {
+                let Some(session) = session
+                    .parse()
+                    .ok()
+                    .and_then(|session: u32| self.install_sessions.remove(&session))
+                else {
+                    return "Error: invalid session\n".to_string();
+                };
+                match session.apks.first() {
+                    Some(apk) => self.install_from(session.user, &apk.clone()),
+                    None => "Failure [INSTALL_FAILED_INVALID_APK]\n".to_string(),
+                }
+            }
+            ["pm", "install-abandon", session] => {
+                if let Ok(session) = session.parse() {
+                    self.install_sessions.remove(&session);
+                }
+                "Success\n".to_string()
+            }
+            ["dumpsys", "package", "changes"] => format!(
+                "Package Changes:\n  Sequence number={}\n",
+                self.package_changes
+            ),
+            ["dumpsys", "package", "packages"] => {
+                let blocks: String = self
+                    .packages
+                    .iter()
+                    .map(|(id, package)| dumpsys_block(id, package))
+                    .collect();
+                format!("Packages:\n{blocks}")
+            }
+            ["dumpsys", "package", id] => match self.packages.get(*id) {
+                Some(package) => format!("Packages:\n{}", dumpsys_block(id, package)),
+                None => format!("Unable to find package: {id}\n"),
+            },
+            [
+                "cmd",
+                "package",
+                "resolve-activity",
+                ..,
+                "android.intent.category.HOME",
+            ] => match self.role(Role::Launcher) {
+                Some(id) => format!(
+                    "priority=0 preferredOrder=0 match=0x108000 specificIndex=-1 \
+                         isDefault=true\n{id}/.Launcher\n"
+                ),
+                None => "No activity found\n".to_string(),
+            },
+            ["settings", "get", "secure", "default_input_method"] => {
+                match self.role(Role::Keyboard) {
+                    Some(id) => format!("{id}/.InputMethod\n"),
+                    None => "null\n".to_string(),
+                }
+            }
+            ["settings", "get", "secure", "sms_default_application"] => {
+                format!("{}\n", self.role(Role::Sms).unwrap_or("null"))
+            }
+            ["telecom", "get-default-dialer"] => {
+                format!("{}\n", self.role(Role::Dialer).unwrap_or_default())
+            }
+            ["du", "-sk", targets @ ..] => {
+                let mut output = String::default();
+                for target in targets {
+                    let size: usize = match self.files.get(*target) {
+                        Some(content) => content.len(),
+                        None => self
+                            .files_in(target)
+                            .iter()
+                            .map(|path| self.files[path].len())
+                            .sum(),
+                    };
+                    output += &format!("{}\t{target}\n", size.div_ceil(1024));
+                }
+                output
+            }
+            ["rm", "-rf", dir] => {
+                let prefix = format!("{dir}/");
+                self.files
+                    .retain(|path, _| path != dir && !path.starts_with(&prefix));
+                String::default()
+            }
+            ["mkdir", "-p", _] => String::default(),
+            ["mv", from, to] => {
+                let prefix = format!("{from}/");
+                let moved: Vec<String> = self
+                    .files
+                    .keys()
+                    .filter(|path| path.starts_with(&prefix))
+                    .cloned()
+                    .collect();
+                for path in moved {
+                    let content = self.files.remove(&path).unwrap_or_default();
+                    self.files
+                        .insert(format!("{to}/{}", &path[prefix.len()..]), content);
+                }
+                String::default()
+            }
+            ["cp", sources @ .., target] => {
+                for source in sources {
+                    let Some(content) = self.files.get(*source).cloned() else {
+                        return format!("cp: {source}: No such file or directory\n");
+                    };
+                    let name = source.rsplit('/').next().unwrap_or(source);
+                    self.files.insert(format!("{target}{name}"), content);
+                }
+                String::default()
+            }
+            ["stat", "-c", "'%s", "%n'", pattern] => {
+                let dir = pattern.trim_end_matches("/*.apk");
+                let apks: Vec<String> = self
+                    .files_in(dir)
+                    .into_iter()
+                    .filter(|path| path.ends_with(".apk"))
+                    .collect();
+                if apks.is_empty() {
+                    return format!("stat: '{pattern}': No such file or directory\n");
+                }
+                apks.iter()
+                    .map(|path| format!("{} {path}\n", self.files[path].len()))
+                    .collect()
+            }
+            _ => format!("/system/bin/sh: {command}: inaccessible or not found\n"),
+        }
+    }
+
+    fn extract_labels(&self, command: &str) -> String {
+        let Some((dex, _)) = command.split_once(' ') else {
+            return String::default();
+        };
+        if !self.files.contains_key(dex) {
+            return "Error: Could not find class 'Main'\n".to_string();
+        }
+        let installed = self
+            .packages
+            .iter()
+            .filter(|(_, package)| !package.installed.is_empty());
+        let mut output = format!("{}\n", json!({ "protocol": extractor::PROTOCOL }));
+        if let Some((_, ids)) = command.split_once(" Main icons ") {
+            let ids: Vec<&str> = ids.split_whitespace().skip(1).collect();
+            for (id, _) in installed.filter(|(id, _)| ids.contains(&id.as_str())) {
+                let icon = STANDARD.encode(format!("PNG {id}"));
+                output += &format!("{}\n", json!({ "id": id, "icon": icon }));
+            }
+        } else {
+            for (id, package) in installed {
+                let app = json!({
+                    "id": id,
+                    "label": package.label,
+                    "uid": 10000,
+                    "version": 1,
+                    "flags": 0,
+                    "enabled": package.disabled.is_empty(),
+                });
+                output += &format!("{app}\n");
+            }
+        }
+        output
+    }
+}
+
+/// The block `dumpsys package` prints about a package.
+fn dumpsys_block(id: &str, package: &FakePackage) -> String {
+    format!(
+        "  Package [{id}] (c0ffee):\n    codePath={}\n    \
+         versionCode=1 minSdk=24 targetSdk=34\n    versionName=1.0\n    \
+         pkgFlags=[ HAS_CODE ALLOW_CLEAR_USER_DATA ]\n    \
+         firstInstallTime=2024-01-01 12:00:00\n    \
+         lastUpdateTime=2024-01-01 12:00:00\n    \
+         installerPackageName=com.android.vending\n",
+        package
+            .path
+            .rsplit_once('/')
+            .map(|(dir, _)| dir)
+            .unwrap_or_default()
+    )
+}
+
+impl AndroidDevice for FakeDevice {
+    fn shell_command_text(&mut self, command: &str) -> Result<String, ShellRunError> {
+        self.commands.push(command.to_string());
+        let scripted = self
+            .scripted
+            .iter()
+            .rev()
+            .find(|(prefix, _)| command.starts_with(prefix.as_str()));
+        match scripted {
+            Some((_, Reply::Print(output))) => Ok(output.clone()),
+            Some((_, Reply::Timeout)) => Err(ShellRunError::Timeout),
+            None => Ok(self.run(command)),
+        }
+    }
+
+    fn push_file(&mut self, stream: &mut dyn Read, path: &str) -> Result<(), ShellRunError> {
+        let mut content = vec![];
+        stream
+            .read_to_end(&mut content)
+            .map_err(|_| ShellRunError::Unrecoverable)?;
+        self.files.insert(path.to_string(), content);
+        Ok(())
+    }
+
+    fn pull_file(&mut self, path: &str, output: &mut dyn Write) -> Result<(), ShellRunError> {
+        let content = self.files.get(path).ok_or(ShellRunError::Unrecoverable)?;
+        output
+            .write_all(content)
+            .map_err(|_| ShellRunError::Unrecoverable)
+    }
+
+    fn reboot(&mut self) -> Result<(), ShellRunError> {
+        self.reboots += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::Duration;
+
+use egui::{Color32, RichText, Spinner, TopBottomPanel};
+use serde::{Deserialize, Serialize};
+
+use crate::ShellRunError;
+use crate::action::Action;
+use crate::adb_shell_text::AndroidDevice;
+use crate::device::DeviceProps;
+use crate::host_store;
+use crate::journal::{self, Record, since_epoch};
+use crate::listview::State;
+use crate::protected::Role;
+
+/// How long a rebooted device has to come back healthy before its last batch is undone.
+pub const BOOT_TIMEOUT: Duration = Duration::from_secs(180);
+const POLL: Duration = Duration::from_secs(5);
+const PENDING_DIR: &str = "reboots";
+
+/// Processes a working phone runs, besides its launcher.
+const ESSENTIAL: [&str; 2] = ["com.android.systemui", "com.android.phone"];
+
+/// A reboot waiting to be verified. It is kept on disk as the connection does not survive it,
+/// and whichever worker next reaches the device finishes the job.
+#[derive(Serialize, Deserialize)]
+pub struct Pending {
+    pub batch: u64,
+    /// Seconds since the unix epoch after which the device counts as not having come back.
+    pub deadline: u64,
+    /// Processes that ran before the reboot and have to run again after it.
+    pub processes: Vec<String>,
+}
+
+/// How a reboot and verify is going, as the worker tells the frontend.
+pub enum Report {
+    Rebooting,
+    /// The device is reachable again and zilch waits for it to finish booting.
+    Waiting,
+    Healthy,
+    RolledBack {
+        problems: Vec<String>,
+        /// Undo actions that failed in turn.
+        failures: Vec<String>,
+    },
+    Failed(String),
+}
+
+impl Report {
+    pub fn done(&self) -> bool {
+        !matches!(self, Report::Rebooting | Report::Waiting)
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Report::Rebooting => f.write_str("rebooting, the device comes back on its own"),
+            Report::Waiting => write!(
+                f,
+                "waiting for the device to finish booting, the last batch is undone \
+                 if it is not healthy {} min after the reboot",
+                BOOT_TIMEOUT.as_secs() / 60
+            ),
+            Report::Healthy => f.write_str("the device came back healthy"),
+            Report::RolledBack { problems, failures } if failures.is_empty() => write!(
+                f,
+                "undid the last batch as the device did not come back healthy: {}",
+                problems.join(", ")
+            ),
+            Report::RolledBack { problems, failures } => write!(
+                f,
+                "tried to undo the last batch as the device did not come back healthy ({}), \
+                 but {}",
+                problems.join(", "),
+                failures.join(", ")
+            ),
+            Report::Failed(e) => write!(f, "reboot and verify failed: {e}"),
+        }
+    }
+}
+
+impl crate::App {
+    /// Shows how the last reboot and verify went, until dismissed.
+    pub fn health_panel(&mut self, ctx: &egui::Context) {
+        let Some(session) = self.session_mut() else {
+            return;
+        };
+        let Some(report) = session.health.as_ref() else {
+            return;
+        };
+
+        let mut dismiss = false;
+        TopBottomPanel::top("health_panel").show(ctx, |ui| {
+            ui.horizontal_wrapped(|ui| {
+                let color = match report {
+                    Report::Healthy => Color32::LIGHT_GREEN,
+                    Report::RolledBack { .. } | Report::Failed(_) => Color32::LIGHT_RED,
+                    Report::Rebooting | Report::Waiting => ui.style().visuals.text_color(),
+                };
+                if !report.done() {
+                    ui.add(Spinner::new());
+                }
+                ui.label(RichText::new(report.to_string()).color(color).size(12.0));
+                if report.done() && ui.small_button("dismiss").clicked() {
+                    dismiss = true;
+                }
+            });
+        });
+        if dismiss {
+            session.health = None;
+        }
+    }
+}
+
+fn pending_path(serial: &str) -> Option<PathBuf> {
+    Some(
+        crate::data_dir()?
+            .join(PENDING_DIR)
+            .join(format!("{}.json", host_store::safe_file_name(serial))),
+    )
+}
+
+fn save(serial: &str, pending: &Pending) -> std::io::Result<()> {
+    let path = pending_path(serial).ok_or_else(|| std::io::Error::other("no data directory"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let text = serde_json::to_string(pending).map_err(std::io::Error::other)?;
+    std::fs::write(path, text)
+}
+
+fn load(serial: &str) -> Option<Pending> {
+    let text = std::fs::read_to_string(pending_path(serial)?).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+fn remove(serial: &str) {
+    if let Some(path) = pending_path(serial)
+        && let Err(e) = std::fs::remove_file(&path)
+    {
+        log::error!("failed to remove {}: {e}", path.display());
+    }
+}
+
+/// Those of `processes` running on the device.
+pub fn running<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+    processes: &[String],
+) -> Result<Vec<String>, ShellRunError> {
+    let mut running = vec![];
+    for process in processes {
+        let pids = device.shell_command_text(&format!("pidof {process}"))?;
+        if pids
+            .split_whitespace()
+            .any(|pid| pid.parse::<u32>().is_ok())
+        {
+            running.push(process.clone());
+        }
+    }
+    Ok(running)
+}
+
+/// What keeps the device from counting as healthy, nothing if it is.
+pub fn problems<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+    processes: &[String],
+) -> Result<Vec<String>, ShellRunError> {
+    if device
+        .shell_command_text("getprop sys.boot_completed")?
+        .trim()
+        != "1"
+    {
+        return Ok(vec!["it did not finish booting".to_string()]);
+    }
+    let running = running(device, processes)?;
+    Ok(processes
+        .iter()
+        .filter(|process| !running.contains(process))
+        .map(|process| format!("{process} is not running"))
+        .collect())
+}
+
+/// The actions undoing a batch, last action first, each with the state it starts from.
+pub fn rollback(records: &[Record], batch: u64) -> Vec<(Action, State)> {
+    records
+        .iter()
+        .filter(|record| record.batch == batch)
+        .rev()
+        .flat_map(|record| {
+            record
+                .undo_actions()
+                .into_iter()
+                .map(|action| (action, record.resulting_state()))
+        })
+        .collect()
+}
+
+/// Reboots the device, after noting which of the processes a healthy device runs are running
+/// now and which batch to undo if they do not come back.
+pub fn reboot<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+    props: &DeviceProps,
+) -> Result<(), String> {
+    let Some(batch) = journal::read(&props.serial)
+        .last()
+        .map(|record| record.batch)
+    else {
+        return Err("zilch has not changed anything on this device".to_string());
+    };
+
+    let mut processes: Vec<String> = ESSENTIAL.map(String::from).into();
+    processes.extend(
+        props
+            .protected
+            .iter()
+            .filter(|(_, role)| **role == Role::Launcher)
+            .map(|(id, _)| id.clone()),
+    );
+    // a tablet has no phone process to miss after the reboot
+    let processes = running(device, &processes).map_err(|e| e.to_string())?;
+
+    let pending = Pending {
+        batch,
+        deadline: (since_epoch() + BOOT_TIMEOUT).as_secs(),
+        processes,
+    };
+    save(&props.serial, &pending).map_err(|e| e.to_string())?;
+
+    // the connection drops as the device goes down, which can look like a failure
+    if let Err(e) = device.reboot() {
+        log::warn!("reboot of {} reported {e}", props.serial);
+    }
+    Ok(())
+}
+
+/// Finishes a reboot and verify started before the device went away, if there is one.
+/// Waits for the device to become healthy or, once the deadline passes, undoes the batch.
+/// Returns whether there was one, as what was read of the device while it booted may be stale.
+pub fn resume<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+    props: &DeviceProps,
+    report: impl Fn(Report),
+) -> Result<bool, ShellRunError> {
+    let Some(pending) = load(&props.serial) else {
+        return Ok(false);
+    };
+    report(Report::Waiting);
+    loop {
+        let problems = problems(device, &pending.processes)?;
+        if problems.is_empty() {
+            remove(&props.serial);
+            report(Report::Healthy);
+            return Ok(true);
+        }
+        if since_epoch().as_secs() >= pending.deadline {
+            let mut batch = journal::Batch::new(&props.serial);
+            let mut failures = vec![];
+            for (action, known) in rollback(&journal::read(&props.serial), pending.batch) {
+                if let Err(e) = batch.apply(action, device, props, known) {
+                    failures.push(e.to_string());
+                }
+            }
+            remove(&props.serial);
+            report(Report::RolledBack { problems, failures });
+            return Ok(true);
+        }
+        sleep(POLL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_device::FakeDevice;
+    use crate::journal::Kind;
+    use crate::users::UserScope;
+
+    fn record(batch: u64, package: &str, action: Kind, previous: State) -> Record {
+        Record {
+            time: 0,
+            batch,
+            serial: "FAKE0001".to_string(),
+            package: package.to_string(),
+            action,
+            users: UserScope::default(),
+            acted_for: vec![0],
+            previous,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn healthy_once_booted_with_its_processes_running() {
+        let mut device = FakeDevice::new(34);
+        let processes = ["com.android.systemui", "com.example.launcher"].map(String::from);
+
+        device.boot_completed = false;
+        assert_eq!(problems(&mut device, &processes).unwrap().len(), 1);
+
+        device.boot_completed = true;
+        assert_eq!(
+            problems(&mut device, &processes).unwrap(),
+            ["com.example.launcher is not running"]
+        );
+
+        device.running.insert("com.example.launcher".to_string());
+        assert!(problems(&mut device, &processes).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rollback_undoes_only_the_batch_newest_first() {
+        let records = [
+            record(1, "com.example.old", Kind::Uninstall, State::Enabled),
+            record(2, "com.example.a", Kind::Uninstall, State::Enabled),
+            record(2, "com.example.b", Kind::Disable, State::Enabled),
+        ];
+        let actions = rollback(&records, 2);
+
+        let undone: Vec<(&str, State)> = actions
+            .iter()
+            .map(|(action, known)| (action.package_id().as_str(), *known))
+            .collect();
+        assert_eq!(
+            undone,
+            [
+                ("com.example.b", State::Disabled),
+                ("com.example.a", State::Uninstalled)
+            ]
+        );
+        assert!(
+            actions
+                .iter()
+                .all(|(action, _)| matches!(action, Action::Revert(..)))
+        );
+    }
+}
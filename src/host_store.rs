@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::adb_shell_text::AndroidDevice;
+use crate::backup;
+use crate::device::DeviceProps;
+use crate::{PackageIdentifier, ShellRunError};
+
+const MANIFEST: &str = "manifest.json";
+/// Where the "backup to computer" toggle is kept between sessions.
+pub const PULL_BACKUPS_KEY: &str = "pull_backups";
+
+/// Lists what has been pulled from one device, keyed by package ID.
+#[derive(Serialize, Deserialize, Default)]
+struct Manifest {
+    packages: BTreeMap<PackageIdentifier, ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestEntry {
+    files: Vec<String>,
+    /// Seconds since the unix epoch.
+    pulled_at: u64,
+}
+
+/// Whether a name from the device can be used as is for a file on this computer, which rules
+/// out anything that could leave the data directory, like `..` or a path separator.
+fn is_safe_file_name(name: &str) -> bool {
+    let safe = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    safe && !name.is_empty() && name != "." && name != ".."
+}
+
+/// Turns a serial, package ID or other name the device printed into a name files can be kept
+/// under on this computer. Names that are not safe as they are get replaced by their hash.
+pub fn safe_file_name(name: &str) -> String {
+    if is_safe_file_name(name) {
+        name.to_string()
+    } else {
+        format!("hashed-{:.16x}", Sha256::digest(name))
+    }
+}
+
+/// The folder holding the APK backups pulled from the device with the given serial.
+pub fn device_dir(serial: &str) -> Option<PathBuf> {
+    Some(
+        crate::data_dir()?
+            .join("backups")
+            .join(safe_file_name(serial)),
+    )
+}
+
+fn read_manifest(dir: &Path) -> Manifest {
+    File::open(dir.join(MANIFEST))
+        .ok()
+        .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+        .unwrap_or_default()
+}
+
+fn write_manifest(dir: &Path, manifest: &Manifest) -> std::io::Result<()> {
+    let file = File::create(dir.join(MANIFEST))?;
+    serde_json::to_writer_pretty(file, manifest).map_err(std::io::Error::other)
+}
+
+/// Copies the on-device backup of a package to this computer.
+pub fn pull<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+    props: &DeviceProps,
+    id: &PackageIdentifier,
+) -> Result<(), ShellRunError> {
+    let not_possible = || ShellRunError::BackupNotPossible(id.clone());
+    let dir = device_dir(&props.serial).ok_or_else(not_possible)?;
+    let package_dir = dir.join(safe_file_name(id));
+    std::fs::create_dir_all(&package_dir).map_err(|_| not_possible())?;
+
+    let mut files = vec![];
+    for (_size, remote_path) in backup::backed_up_apks(device, id)? {
+        let file_name = remote_path.rsplit('/').next().unwrap_or(&remote_path);
+        // the name is used on the device again when pushing, so it has to be kept as it is
+        if !is_safe_file_name(file_name) {
+            log::error!("refused to pull {remote_path:?}, its name is not safe to keep");
+            return Err(not_possible());
+        }
+        let mut file = File::create(package_dir.join(file_name)).map_err(|_| not_possible())?;
+        device
+            .pull_file(&remote_path, &mut file)
+            .map_err(|_| not_possible())?;
+        files.push(file_name.to_string());
+    }
+    if files.is_empty() {
+        return Err(not_possible());
+    }
+
+    let pulled_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let mut manifest = read_manifest(&dir);
+    manifest
+        .packages
+        .insert(id.clone(), ManifestEntry { files, pulled_at });
+    write_manifest(&dir, &manifest).map_err(|_| not_possible())
+}
+
+/// Puts a package's APKs back into its on-device backup directory.
+/// Returns false if this computer has no backup of the package.
+pub fn push<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+    props: &DeviceProps,
+    id: &PackageIdentifier,
+) -> Result<bool, ShellRunError> {
+    let Some(dir) = device_dir(&props.serial) else {
+        return Ok(false);
+    };
+    let manifest = read_manifest(&dir);
+    let Some(entry) = manifest.packages.get(id) else {
+        return Ok(false);
+    };
+
+    let remote_dir = backup::backup_dir(id);
+    for file_name in entry.files.iter() {
+        let failed = || ShellRunError::RevertFailed(id.clone(), format!("cannot push {file_name}"));
+        if !is_safe_file_name(file_name) {
+            return Err(failed());
+        }
+        let file =
+            File::open(dir.join(safe_file_name(id)).join(file_name)).map_err(|_| failed())?;
+        device
+            .push_file(
+                &mut BufReader::new(file),
+                &format!("{remote_dir}/{file_name}"),
+            )
+            .map_err(|_| failed())?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_from_the_device_stay_inside_the_data_directory() {
+        for name in [
+            "R58M12AB3CD",
+            "192.168.1.20-5555_adb",
+            "com.example.app",
+            "base.apk",
+        ] {
+            assert_eq!(safe_file_name(name), name);
+        }
+        for name in [
+            "..",
+            ".",
+            "",
+            "../../etc",
+            "a/b",
+            "C:\\x",
+            "192.168.1.20:5555",
+        ] {
+            let safe = safe_file_name(name);
+            assert!(safe.starts_with("hashed-"), "{name} became {safe}");
+            assert_eq!(safe.len(), "hashed-".len() + 16);
+        }
+        assert_ne!(safe_file_name("a/b"), safe_file_name("a\\b"));
+    }
+}
@@ -0,0 +1,164 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::adb_shell_text::AndroidDevice;
+use crate::{PackageIdentifier, ShellRunError};
+use crate::{extractor, host_store};
+
+const ICON_DIR: &str = "icons";
+/// Edge length in pixels the helper renders icons at.
+const SIZE: u32 = 48;
+/// Packages the helper is asked about at once, keeping its command line short.
+const BATCH: usize = 40;
+
+/// A PNG, shared between the frontend and the image loader.
+pub type Icon = Arc<[u8]>;
+
+/// The file name of an icon, which changes along with the version of its package.
+fn file_name(id: &str, version: Option<i64>) -> String {
+    let id = host_store::safe_file_name(id);
+    match version {
+        Some(version) => format!("{id}@{version}.png"),
+        None => format!("{id}.png"),
+    }
+}
+
+/// The URI egui caches the decoded icon under.
+pub fn uri(serial: &str, id: &str, version: Option<i64>) -> String {
+    format!("bytes://icons/{serial}/{}", file_name(id, version))
+}
+
+fn device_dir(serial: &str) -> Option<PathBuf> {
+    Some(
+        crate::data_dir()?
+            .join(ICON_DIR)
+            .join(host_store::safe_file_name(serial)),
+    )
+}
+
+fn cached(serial: &str, id: &str, version: Option<i64>) -> Option<Icon> {
+    let path = device_dir(serial)?.join(file_name(id, version));
+    std::fs::read(path).ok().map(Icon::from)
+}
+
+/// Caches the icon, dropping those of other versions of the package.
+fn store(serial: &str, id: &str, version: Option<i64>, png: &[u8]) -> std::io::Result<()> {
+    let dir = device_dir(serial).ok_or_else(|| std::io::Error::other("no data directory"))?;
+    std::fs::create_dir_all(&dir)?;
+    let name = file_name(id, version);
+    let id = host_store::safe_file_name(id);
+    for file in std::fs::read_dir(&dir)?.flatten() {
+        let other = file.file_name();
+        let other = other.to_string_lossy();
+        if other != name && (other == format!("{id}.png") || other.starts_with(&format!("{id}@"))) {
+            std::fs::remove_file(file.path())?;
+        }
+    }
+    std::fs::write(dir.join(name), png)
+}
+
+/// Icons of the packages, from the cache on this computer or else rendered on the device.
+/// Rendering takes a while, so it is done a batch at a time between the worker's other jobs.
+pub struct Fetcher {
+    serial: String,
+    /// Packages still to render, with their versions.
+    queue: Vec<(PackageIdentifier, Option<i64>)>,
+}
+
+impl Fetcher {
+    pub fn new(serial: &str) -> Self {
+        Fetcher {
+            serial: serial.to_string(),
+            queue: vec![],
+        }
+    }
+
+    /// Queues the icons of the packages for rendering, returning those already cached.
+    pub fn request(
+        &mut self,
+        packages: impl IntoIterator<Item = (PackageIdentifier, Option<i64>)>,
+    ) -> Vec<(PackageIdentifier, Icon)> {
+        let mut icons = vec![];
+        for (id, version) in packages {
+            match cached(&self.serial, &id, version) {
+                Some(icon) => icons.push((id, icon)),
+                None => self.queue.push((id, version)),
+            }
+        }
+        icons
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Renders the next batch of queued icons on the device. Packages without an icon are
+    /// left out.
+    pub fn fetch_batch<D: AndroidDevice + ?Sized>(
+        &mut self,
+        device: &mut D,
+    ) -> Result<Vec<(PackageIdentifier, Icon)>, ShellRunError> {
+        let batch: Vec<_> = self.queue.drain(..BATCH.min(self.queue.len())).collect();
+        if batch.is_empty() {
+            return Ok(vec![]);
+        }
+        let ids: Vec<&str> = batch.iter().map(|(id, _)| id.as_str()).collect();
+        let output = device.shell_command_text(&format!(
+            "CLASSPATH=/data/local/tmp/extractor.dex app_process / Main icons {SIZE} {}",
+            ids.join(" ")
+        ))?;
+        let rendered = extractor::icons(&output)?;
+
+        let mut icons = vec![];
+        for (id, png) in rendered {
+            let Some(&(_, version)) = batch.iter().find(|(queued, _)| *queued == id) else {
+                continue;
+            };
+            if let Err(e) = store(&self.serial, &id, version, &png) {
+                log::warn!("failed to cache the icon of {id}: {e}");
+            }
+            icons.push((id, png.into()));
+        }
+        Ok(icons)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_device::{FakeDevice, Reply};
+    use crate::prepare_device;
+
+    /// What Main.java printed for an icon: org.json escapes the slashes of the base64.
+    const RENDERED: &str = "{\"protocol\":1}\n\
+        {\"id\":\"com.example.red\",\"icon\":\"iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR4\
+        nGP4z8DwHwAFAAH\\/iZk9HQAAAABJRU5ErkJggg==\"}\n";
+
+    #[test]
+    fn file_names_stay_inside_the_icon_directory() {
+        assert_eq!(file_name("com.example.a", Some(3)), "com.example.a@3.png");
+        let name = file_name("../../evil", Some(3));
+        assert!(name.starts_with("hashed-") && !name.contains('/'), "{name}");
+    }
+
+    #[test]
+    fn reads_icons_as_the_extractor_prints_them() {
+        let mut device = FakeDevice::new(34).with_package("com.example.red", "Red");
+        prepare_device(&mut device).unwrap();
+        device.script("CLASSPATH=", Reply::Print(RENDERED.to_string()));
+        let serial = "FAKE-ICONS";
+        if let Some(dir) = device_dir(serial) {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+        let mut fetcher = Fetcher::new(serial);
+        let red = [("com.example.red".to_string(), Some(1))];
+
+        assert!(fetcher.request(red.clone()).is_empty());
+        let icons = fetcher.fetch_batch(&mut device).unwrap();
+        assert!(fetcher.is_idle());
+        assert_eq!(icons.len(), 1);
+        let image = image::load_from_memory(&icons[0].1).unwrap().to_rgba8();
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(fetcher.request(red).len(), 1);
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use egui::{Button, Color32, Id, Modal, RichText, ScrollArea};
+use serde::{Deserialize, Serialize};
+
+use crate::action::Action;
+use crate::adb_shell_text::AndroidDevice;
+use crate::device::DeviceProps;
+use crate::listview::State;
+use crate::profile::Change;
+use crate::users::UserScope;
+use crate::{Package, PackageIdentifier, ShellRunError};
+
+const JOURNAL: &str = "journal.jsonl";
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Uninstall,
+    Revert,
+    Disable,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Uninstall => "uninstall",
+            Kind::Revert => "revert",
+            Kind::Disable => "disable",
+        }
+    }
+}
+
+/// One action zilch carried out, or tried to, on a device.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Record {
+    /// Seconds since the unix epoch.
+    pub time: u64,
+    /// Actions queued together share a batch.
+    pub batch: u64,
+    pub serial: String,
+    pub package: PackageIdentifier,
+    pub action: Kind,
+    pub users: UserScope,
+    /// The users the action went through, which with all users in scope are only those
+    /// that had the package.
+    #[serde(default)]
+    pub acted_for: Vec<u32>,
+    pub previous: State,
+    /// What went wrong, or nothing if the action succeeded.
+    pub error: Option<String>,
+}
+
+impl Record {
+    fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// The state the action left the package in, if it succeeded.
+    pub fn resulting_state(&self) -> State {
+        match self.action {
+            Kind::Uninstall => State::Uninstalled,
+            Kind::Revert => State::Enabled,
+            Kind::Disable => State::Disabled,
+        }
+    }
+
+    /// Actions that put the package back into the state it had before this record.
+    pub fn undo_actions(&self) -> Vec<Action> {
+        if !self.succeeded() {
+            return vec![];
+        }
+        change_actions(
+            &self.package,
+            self.users,
+            self.resulting_state(),
+            self.previous,
+        )
+    }
+}
+
+fn change_actions(id: &PackageIdentifier, users: UserScope, from: State, to: State) -> Vec<Action> {
+    if from == to {
+        return vec![];
+    }
+    let change = Change {
+        id: id.clone(),
+        from,
+        to,
+    };
+    // the journal does not know where the APK lived, uninstalling only needs the ID
+    let package = Package {
+        id: id.clone(),
+        path: String::default(),
+        label: String::default(),
+        size: None,
+        version: None,
+    };
+    change.actions(&package, users)
+}
+
+/// Actions putting every package the records touched back into the state it had before
+/// the first of them that succeeded, straight from its current state rather than undoing
+/// each record in turn. `current` tells the state a package is in now for the given users,
+/// when known, or else the last successful record is taken at its word.
+pub fn undo_all(
+    records: &[Record],
+    current: impl Fn(&PackageIdentifier, UserScope) -> Option<State>,
+) -> Vec<Action> {
+    // the first and last successful record of each package, in the order packages came up
+    let mut touched: Vec<(&Record, &Record)> = vec![];
+    for record in records.iter().filter(|record| record.succeeded()) {
+        let same = |(first, _): &&mut (&Record, &Record)| {
+            first.package == record.package && first.users == record.users
+        };
+        match touched.iter_mut().find(same) {
+            Some((_, last)) => *last = record,
+            None => touched.push((record, record)),
+        }
+    }
+    touched
+        .into_iter()
+        .flat_map(|(first, last)| {
+            let from = current(&first.package, first.users).unwrap_or(last.resulting_state());
+            change_actions(&first.package, first.users, from, first.previous)
+        })
+        .collect()
+}
+
+pub fn since_epoch() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+fn journal_path() -> Option<PathBuf> {
+    Some(crate::data_dir()?.join(JOURNAL))
+}
+
+fn append(record: &Record) -> std::io::Result<()> {
+    let path = journal_path().ok_or_else(|| std::io::Error::other("no data directory"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut line = serde_json::to_string(record).map_err(std::io::Error::other)?;
+    line.push('\n');
+    // a single write keeps lines from several workers from interleaving
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())
+}
+
+/// Everything the journal holds about the device with the given serial, oldest first.
+pub fn read(serial: &str) -> Vec<Record> {
+    let Some(file) = journal_path().and_then(|path| File::open(path).ok()) else {
+        return vec![];
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str::<Record>(&line).ok())
+        .filter(|record| record.serial == serial)
+        .collect()
+}
+
+/// The users the package was last uninstalled for on the device with the given serial.
+pub fn uninstalled_for(serial: &str, id: &str) -> Vec<u32> {
+    read(serial)
+        .into_iter()
+        .rev()
+        .find(|record| {
+            record.package == id && record.succeeded() && matches!(record.action, Kind::Uninstall)
+        })
+        .map(|record| record.acted_for)
+        .unwrap_or_default()
+}
+
+/// Actions applied together, each recorded in the journal as it runs.
+pub struct Batch {
+    id: u64,
+    serial: String,
+    /// States set by earlier actions of this batch, which the caller does not know about yet.
+    states: BTreeMap<PackageIdentifier, State>,
+}
+
+impl Batch {
+    pub fn new(serial: &str) -> Self {
+        Batch {
+            id: since_epoch().as_millis() as u64,
+            serial: serial.to_string(),
+            states: BTreeMap::new(),
+        }
+    }
+
+    /// Applies the action and records it. `known` is the package state as the caller last saw it.
+    pub fn apply<D: AndroidDevice + ?Sized>(
+        &mut self,
+        action: Action,
+        device: &mut D,
+        props: &DeviceProps,
+        known: State,
+    ) -> Result<(), ShellRunError> {
+        let mut record = Record {
+            time: since_epoch().as_secs(),
+            batch: self.id,
+            serial: self.serial.clone(),
+            package: action.package_id().clone(),
+            action: match action {
+                Action::Uninstall(..) => Kind::Uninstall,
+                Action::Revert(..) => Kind::Revert,
+                Action::Disable(..) => Kind::Disable,
+            },
+            users: action.users(),
+            acted_for: vec![],
+            previous: self
+                .states
+                .get(action.package_id())
+                .copied()
+                .unwrap_or(known),
+            error: None,
+        };
+
+        let resulting_state = action.resulting_state();
+        let result = match action.apply_on_device(device, props) {
+            Ok(acted_for) => {
+                record.acted_for = acted_for;
+                self.states.insert(record.package.clone(), resulting_state);
+                Ok(())
+            }
+            Err(e) => {
+                record.error = Some(match e.output() {
+                    Some(output) => format!("{e}: {output}"),
+                    None => e.to_string(),
+                });
+                Err(e)
+            }
+        };
+        if let Err(e) = append(&record) {
+            log::error!("failed to write to the journal: {e}");
+        }
+        result
+    }
+}
+
+/// How long ago something happened, roughly, given in seconds since the unix epoch.
+pub fn age(time: u64) -> String {
+    let seconds = since_epoch().as_secs().saturating_sub(time);
+    match seconds {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} min ago", seconds / 60),
+        3600..86400 => format!("{} h ago", seconds / 3600),
+        _ => format!("{} days ago", seconds / 86400),
+    }
+}
+
+/// The history window, which reloads the journal once the actions it queued are done.
+pub struct History {
+    records: Vec<Record>,
+    stale: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            records: vec![],
+            stale: true,
+        }
+    }
+}
+
+impl crate::App {
+    pub fn history_view(&mut self, ctx: &egui::Context) {
+        let Some(serial) = self.current_device.clone() else {
+            return;
+        };
+        let Some(session) = self.sessions.get_mut(&serial) else {
+            return;
+        };
+        let Some(history) = self.history.as_mut() else {
+            return;
+        };
+        if history.stale && !session.busy {
+            history.records = read(&serial);
+            history.stale = false;
+        }
+
+        let mut undo: Vec<&Record> = vec![];
+        let mut undo_batch: &[Record] = &[];
+        let mut close = false;
+        let modal = Modal::new(Id::new("history_view")).show(ctx, |ui| {
+            ui.heading("History");
+            if history.records.is_empty() {
+                ui.label("Zilch has not changed anything on this device yet.");
+            }
+
+            let can_undo = !session.busy && history.records.iter().any(Record::succeeded);
+            if ui
+                .add_enabled(can_undo, Button::new("undo everything"))
+                .on_hover_text("put back every package zilch ever changed on this device")
+                .clicked()
+            {
+                undo_batch = &history.records;
+            }
+
+            ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                let records = &history.records;
+                // newest batches first, each record in the order it ran
+                for batch in records.chunk_by(|a, b| a.batch == b.batch).rev() {
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{}, {} actions", age(batch[0].time), batch.len()));
+                        let can_undo = !session.busy && batch.iter().any(Record::succeeded);
+                        if ui
+                            .add_enabled(can_undo, Button::new("undo batch").small())
+                            .clicked()
+                        {
+                            undo_batch = batch;
+                        }
+                    });
+
+                    for record in batch.iter() {
+                        ui.horizontal(|ui| {
+                            let can_undo = !session.busy && !record.undo_actions().is_empty();
+                            if ui
+                                .add_enabled(can_undo, Button::new("undo").small())
+                                .clicked()
+                            {
+                                undo.push(record);
+                            }
+                            ui.label(
+                                RichText::new(format!(
+                                    "{} {} for {}, was {}",
+                                    record.action.name(),
+                                    record.package,
+                                    record.users,
+                                    record.previous.name()
+                                ))
+                                .monospace()
+                                .size(10.0),
+                            );
+                        });
+                        if let Some(error) = record.error.as_ref() {
+                            ui.label(RichText::new(error).color(Color32::LIGHT_RED).size(10.0));
+                        }
+                    }
+                }
+            });
+
+            ui.separator();
+            if ui.button("close").clicked() {
+                close = true;
+            }
+        });
+
+        let mut actions: Vec<Action> = undo.into_iter().flat_map(Record::undo_actions).collect();
+        actions.extend(undo_all(undo_batch, |id, users| {
+            // the list only knows the state for the users it shows
+            let entry = session
+                .entries
+                .get(id)
+                .filter(|_| users == session.user_scope);
+            entry.map(|entry| entry.state)
+        }));
+        if !actions.is_empty() {
+            history.stale = true;
+        }
+
+        if close || modal.should_close() {
+            self.history = None;
+        }
+        // undoing a revert uninstalls or disables again, which deserves the same look over
+        self.confirm_actions(actions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOAT: &str = "com.example.bloat";
+    const TRACKER: &str = "com.example.tracker";
+
+    fn record(package: &str, action: Kind, previous: State, error: Option<&str>) -> Record {
+        Record {
+            time: 0,
+            batch: 1,
+            serial: "FAKE0001".to_string(),
+            package: package.to_string(),
+            action,
+            users: UserScope::User(0),
+            acted_for: vec![0],
+            previous,
+            error: error.map(str::to_string),
+        }
+    }
+
+    fn describe(actions: Vec<Action>) -> Vec<String> {
+        actions.iter().map(Action::to_string).collect()
+    }
+
+    #[test]
+    fn undoing_a_record_restores_its_previous_state() {
+        let uninstall = record(BLOAT, Kind::Uninstall, State::Disabled, None);
+        assert_eq!(
+            describe(uninstall.undo_actions()),
+            [
+                format!("revert {BLOAT} for user 0"),
+                format!("disable {BLOAT} for user 0")
+            ]
+        );
+
+        let failed = record(BLOAT, Kind::Disable, State::Enabled, Some("refused"));
+        assert!(failed.undo_actions().is_empty());
+        let no_change = record(BLOAT, Kind::Revert, State::Enabled, None);
+        assert!(no_change.undo_actions().is_empty());
+    }
+
+    #[test]
+    fn undoing_a_batch_goes_back_to_each_first_state() {
+        let records = [
+            record(BLOAT, Kind::Disable, State::Enabled, None),
+            record(TRACKER, Kind::Uninstall, State::Enabled, Some("refused")),
+            record(BLOAT, Kind::Uninstall, State::Disabled, None),
+            record(TRACKER, Kind::Disable, State::Enabled, None),
+            record(TRACKER, Kind::Uninstall, State::Disabled, None),
+        ];
+
+        // one action per package, straight from its last recorded state
+        assert_eq!(
+            describe(undo_all(&records, |_, _| None)),
+            [
+                format!("revert {BLOAT} for user 0"),
+                format!("revert {TRACKER} for user 0")
+            ]
+        );
+
+        // a package put back by hand since needs nothing
+        let current = |id: &PackageIdentifier, _| (id == BLOAT).then_some(State::Enabled);
+        assert_eq!(
+            describe(undo_all(&records, current)),
+            [format!("revert {TRACKER} for user 0")]
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use crate::{Metadata, categories, metadata};
+
+const FILE_NAME: &str = "uad_lists.json";
+const PATH_VARIABLE: &str = "ZILCH_UAD_LISTS";
+
+/// A package in Universal Android Debloater's `uad_lists.json`. Fields zilch has no use for,
+/// like `list`, `dependencies` and `neededBy`, are skipped.
+#[derive(Deserialize)]
+struct UadPackage {
+    /// Only present in the older array format.
+    id: Option<String>,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    removal: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UadLists {
+    ByPackage(HashMap<String, UadPackage>),
+    Array(Vec<UadPackage>),
+}
+
+struct Loaded {
+    path: PathBuf,
+    packages: HashMap<String, Metadata>,
+}
+
+static LOADED: OnceLock<Option<Loaded>> = OnceLock::new();
+
+/// Where the metadata of a package comes from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    BuiltIn,
+    /// The UAD list loaded at startup.
+    File,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self, loaded()) {
+            (Source::File, Some(loaded)) => write!(f, "{}", loaded.path.display()),
+            _ => f.write_str("built-in list"),
+        }
+    }
+}
+
+fn loaded() -> Option<&'static Loaded> {
+    LOADED.get()?.as_ref()
+}
+
+fn parse(text: &str) -> Result<HashMap<String, Metadata>, serde_json::Error> {
+    let packages: Vec<(String, UadPackage)> = match serde_json::from_str(text)? {
+        UadLists::ByPackage(packages) => packages.into_iter().collect(),
+        UadLists::Array(packages) => packages
+            .into_iter()
+            .filter_map(|package| Some((package.id.clone()?, package)))
+            .collect(),
+    };
+    Ok(packages
+        .into_iter()
+        .map(|(id, package)| {
+            let metadata = Metadata {
+                // loaded once and kept for as long as zilch runs
+                description: package.description.leak(),
+                removal: categories::name_to_value(&package.removal)
+                    .unwrap_or(categories::UNIDENTIFIED),
+            };
+            (id, metadata)
+        })
+        .collect())
+}
+
+/// Places to look for a file, in order: the given path, the environment variable,
+/// the working directory and zilch's data directory.
+pub fn candidates(path: Option<&Path>, variable: &str, file_name: &str) -> Vec<PathBuf> {
+    let mut candidates: Vec<PathBuf> = path.into_iter().map(Path::to_path_buf).collect();
+    candidates.extend(std::env::var_os(variable).map(PathBuf::from));
+    candidates.push(PathBuf::from(file_name));
+    candidates.extend(crate::data_dir().map(|dir| dir.join(file_name)));
+    candidates
+}
+
+/// Loads the first UAD list found to merge over the built-in one. Only the first call does anything.
+pub fn load(path: Option<&Path>) {
+    LOADED.get_or_init(|| {
+        for path in candidates(path, PATH_VARIABLE, FILE_NAME) {
+            if !path.is_file() {
+                continue;
+            }
+            let parsed = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| parse(&text).map_err(|e| e.to_string()));
+            match parsed {
+                Ok(packages) => return Some(Loaded { path, packages }),
+                Err(e) => log::warn!("ignoring package list {}: {e}", path.display()),
+            }
+        }
+        None
+    });
+}
+
+/// Looks a package up in the loaded list, falling back to the built-in one.
+pub fn lookup(id: &str) -> Option<(&'static Metadata, Source)> {
+    if let Some(metadata) = loaded().and_then(|loaded| loaded.packages.get(id)) {
+        return Some((metadata, Source::File));
+    }
+    metadata::STORE
+        .get(id)
+        .map(|metadata| (metadata, Source::BuiltIn))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lists_keyed_by_package() {
+        let packages = parse(
+            r#"{
+                "com.example.bloat": {
+                    "list": "Oem",
+                    "description": "Preinstalled bloat",
+                    "dependencies": [],
+                    "neededBy": [],
+                    "labels": [],
+                    "removal": "Recommended"
+                },
+                "com.example.core": { "list": "Aosp", "removal": "Unsafe" }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            packages["com.example.bloat"].description,
+            "Preinstalled bloat"
+        );
+        assert_eq!(
+            packages["com.example.bloat"].removal,
+            categories::RECOMMENDED
+        );
+        assert_eq!(packages["com.example.core"].removal, categories::UNSAFE);
+    }
+
+    #[test]
+    fn parses_the_older_array_format() {
+        let packages = parse(
+            r#"[
+                { "id": "com.example.bloat", "description": "", "removal": "Advanced" },
+                { "description": "no id", "removal": "Expert" }
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(packages.len(), 1);
+        assert_eq!(packages["com.example.bloat"].removal, categories::ADVANCED);
+    }
+
+    #[test]
+    fn unknown_removal_is_unidentified() {
+        let packages = parse(r#"{ "com.example": { "removal": "Unlisted" } }"#).unwrap();
+        assert_eq!(packages["com.example"].removal, categories::UNIDENTIFIED);
+    }
+}
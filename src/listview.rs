@@ -1,114 +1,214 @@
 use crate::Metadata;
 use crate::Package;
 use crate::categories;
-use egui::{Align, Button, Color32, Layout, RichText, Sense, Stroke, Style, text::LayoutJob};
+use crate::details::PackageDetails;
+use crate::lists;
+use crate::overrides::{self, Override};
+use std::sync::mpsc::Sender;
+
+use egui::{Grid, RichText, Spinner};
+use serde::{Deserialize, Serialize};
 
 #[repr(u8)]
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum State {
     Enabled = 0b001,
     Uninstalled = 0b010,
     Disabled = 0b110,
 }
 
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Enabled => "enabled",
+            State::Disabled => "disabled",
+            State::Uninstalled => "uninstalled",
+        }
+    }
+}
+
 pub struct Entry {
     pub package: Package,
-    pub expand_triggered: bool,
     pub state: State,
     pub selected: bool,
     pub metadata: Option<&'static Metadata>,
+    pub source: Option<lists::Source>,
+    pub overrides: Option<&'static Override>,
+    /// Read from the device the first time the entry is expanded.
+    pub details: Option<Result<PackageDetails, String>>,
+    pub details_requested: bool,
 }
 
 impl Entry {
-    pub fn render(&mut self, ui: &mut egui::Ui) {
-        let id = ui.make_persistent_id(format!("{}_state", self.package.id));
-        let mut state =
-            egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, false);
+    /// The removal category, as the team's overrides or else the package lists rate it.
+    pub fn removal(&self) -> u8 {
+        self.overrides
+            .and_then(Override::removal)
+            .or(self.metadata.map(|m| m.removal))
+            .unwrap_or(categories::UNIDENTIFIED)
+    }
 
-        if self.expand_triggered {
-            state.toggle(ui);
-            self.expand_triggered = false;
+    /// A bare entry for tests, without label, path, size, metadata or details.
+    #[cfg(test)]
+    pub fn test(id: &str, state: State) -> Self {
+        Entry {
+            package: Package {
+                id: id.to_string(),
+                path: String::default(),
+                label: String::default(),
+                size: None,
+                version: None,
+            },
+            state,
+            selected: false,
+            metadata: None,
+            source: None,
+            overrides: None,
+            details: None,
+            details_requested: false,
         }
+    }
 
-        let header = ui.horizontal(|ui| {
-            ui.style_mut().spacing.button_padding = egui::vec2(20.0, 10.0);
-            ui.with_layout(Layout::top_down_justified(egui::Align::LEFT), |ui| {
-                // supply faint versions of the colors for disabled apps
-                let faint_bg = ui.style().visuals.faint_bg_color;
-                let selection_bg = ui.style().visuals.selection.bg_fill;
-                let fg = ui.style().visuals.selection.stroke.color;
-                let faint_selection_bg = selection_bg.lerp_to_gamma(faint_bg, 0.6);
-                let faint_fg = fg.lerp_to_gamma(faint_bg, 0.6);
+    pub fn tags(&self) -> &[String] {
+        self.overrides
+            .map(|o| o.tags.as_slice())
+            .unwrap_or_default()
+    }
 
-                let response = ui.add(self.button(
-                    faint_selection_bg,
-                    selection_bg,
-                    faint_fg,
-                    categories::value_to_name(self.metadata.map(|m| m.removal).unwrap_or_default()),
-                ));
-                let id = ui.make_persistent_id(format!("{}_interact", self.package.id));
-                if ui
-                    .interact(response.rect, id, Sense::click())
-                    .double_clicked()
-                {
-                    self.expand_triggered = true;
-                    self.selected ^= true;
-                } else if ui.interact(response.rect, id, Sense::click()).clicked() {
-                    self.selected ^= true;
+    /// What the details panel shows about the entry: descriptions, overrides and package details.
+    pub fn render_body(&mut self, ui: &mut egui::Ui, details_tx: &Sender<(String, String)>) {
+        ui.add_space(4.0);
+        ui.label(
+            RichText::new(
+                self.metadata
+                    .map(|m| m.description)
+                    .unwrap_or("Description unavailable."),
+            )
+            .size(12.0),
+        );
+        if let Some(source) = self.source {
+            ui.label(RichText::new(format!("from {source}")).weak().size(10.0));
+        }
+        if let Some(overrides) = self.overrides {
+            self.render_overrides(ui, overrides);
+        }
+
+        ui.add_space(4.0);
+        match self.details.as_ref() {
+            Some(Ok(details)) => self.render_details(ui, details),
+            Some(Err(e)) => {
+                ui.label(RichText::new(format!("Details unavailable: {e}")).size(10.0));
+            }
+            None => {
+                if !self.details_requested {
+                    self.details_requested = true;
+                    let _ = details_tx.send((self.package.id.clone(), self.package.path.clone()));
+                }
+                ui.add(Spinner::new());
+            }
+        }
+        ui.add_space(4.0);
+    }
+
+    fn render_details(&self, ui: &mut egui::Ui, details: &PackageDetails) {
+        let unknown = || "unknown".to_string();
+        let version = match (details.version_name.as_ref(), details.version_code) {
+            (Some(name), Some(code)) => format!("{name} ({code})"),
+            (Some(name), None) => name.clone(),
+            (None, Some(code)) => code.to_string(),
+            (None, None) => unknown(),
+        };
+        let kind = match (details.system, details.updated_system_app) {
+            (_, true) => "updated system app",
+            (true, false) => "system app",
+            (false, false) => "user app",
+        };
+        let rows = [
+            ("Version", version),
+            (
+                "Installer",
+                details.installer.clone().unwrap_or_else(unknown),
+            ),
+            ("Partition", format!("{} ({kind})", details.partition)),
+            (
+                "Installed",
+                details.first_install_time.clone().unwrap_or_else(unknown),
+            ),
+            (
+                "Updated",
+                details.last_update_time.clone().unwrap_or_else(unknown),
+            ),
+            (
+                "Size",
+                details.size.map(format_size).unwrap_or_else(unknown),
+            ),
+        ];
+
+        Grid::new(format!("{}_details", self.package.id))
+            .num_columns(2)
+            .show(ui, |ui| {
+                for (name, value) in rows {
+                    ui.label(RichText::new(name).weak().size(10.0));
+                    ui.label(RichText::new(value).monospace().size(10.0));
+                    ui.end_row();
                 }
             });
-        });
+    }
 
-        state.show_body_indented(&header.response, ui, |ui| {
-            ui.add_space(4.0);
+    fn render_overrides(&self, ui: &mut egui::Ui, overrides: &Override) {
+        let team_color = ui.style().visuals.warn_fg_color;
+        ui.add_space(4.0);
+        if let Some(removal) = overrides.removal() {
+            let upstream = self
+                .metadata
+                .map(|m| m.removal)
+                .unwrap_or(categories::UNIDENTIFIED);
             ui.label(
-                RichText::new(
-                    self.metadata
-                        .map(|m| m.description)
-                        .unwrap_or("Description unavailable."),
-                )
+                RichText::new(format!(
+                    "Team rating: {} (lists say {})",
+                    categories::value_to_name(removal),
+                    categories::value_to_name(upstream)
+                ))
+                .color(team_color)
                 .size(12.0),
             );
-            ui.add_space(4.0);
-        });
-    }
-
-    fn button<'a>(
-        &self,
-        faint_bg: Color32,
-        selection_bg: Color32,
-        faint_fg: Color32,
-        right_text: &'a str,
-    ) -> Button<'a> {
-        let mut job = LayoutJob::default();
-        let mut label = RichText::new(format!("{}\n", self.package.label)).size(12.0);
-        let mut package_id = RichText::new(&self.package.id).monospace().size(10.0);
-        let mut right_text = RichText::new(right_text);
-
-        if self.state != State::Enabled {
-            label = label.strikethrough().color(faint_fg);
-            package_id = package_id.strikethrough().color(faint_fg);
-            right_text = right_text.color(faint_fg);
         }
-
-        label.append_to(
-            &mut job,
-            &Style::default(),
-            egui::FontSelection::Default,
-            Align::Min,
-        );
-        package_id.append_to(
-            &mut job,
-            &Style::default(),
-            egui::FontSelection::Default,
-            Align::Min,
-        );
-        let button = Button::selectable(self.selected, job);
-        if self.state != State::Enabled {
-            button.stroke(Stroke::new(1.0, selection_bg)).fill(faint_bg)
-        } else {
-            button
+        if let Some(description) = overrides.description.as_ref() {
+            ui.label(
+                RichText::new(format!("Team description: {description}"))
+                    .color(team_color)
+                    .size(12.0),
+            );
+        }
+        if let Some(note) = overrides.note.as_ref() {
+            ui.label(
+                RichText::new(format!("Note: {note}"))
+                    .color(team_color)
+                    .size(12.0),
+            );
         }
-        .right_text(right_text)
+        if !overrides.tags.is_empty() {
+            ui.horizontal_wrapped(|ui| {
+                for tag in overrides.tags.iter() {
+                    ui.label(RichText::new(format!("#{tag}")).monospace().size(10.0));
+                }
+            });
+        }
+        if let Some(path) = overrides::path() {
+            ui.label(
+                RichText::new(format!("overridden in {}", path.display()))
+                    .weak()
+                    .size(10.0),
+            );
+        }
+    }
+}
+
+pub fn format_size(kib: u64) -> String {
+    match kib {
+        0..1024 => format!("{kib} KiB"),
+        1024..1048576 => format!("{:.1} MiB", kib as f64 / 1024.0),
+        _ => format!("{:.1} GiB", kib as f64 / 1048576.0),
     }
 }
@@ -9,39 +9,81 @@ use std::{
     time::Duration,
 };
 
-use adb_client::{ADBDeviceExt, ADBUSBDevice};
+use adb_client::ADBDeviceExt;
 use eframe::egui;
 use egui::{Align, CentralPanel, Label, Spinner, TextEdit, TopBottomPanel};
 use egui_alignments::{center_horizontal, column};
 
-use crate::{action::Action, adb_shell_text::ShellCommandText};
+use crate::{action::Action, adb_shell_text::AndroidDevice, users::UserScope};
 mod action;
 mod action_bar;
 mod adb_shell_text;
+mod backup;
 mod categories;
+mod cli;
+mod confirm;
+mod connect_dialog;
+mod crash_watch;
+mod details;
+mod device;
+mod dry_run;
+mod extractor;
+#[cfg(test)]
+mod fake_device;
+mod health;
+mod host_store;
+mod icons;
+mod journal;
+mod lists;
 mod listview;
 mod metadata;
+mod notifications;
+mod overrides;
+mod package_watch;
+mod profile;
+mod protected;
+mod selection;
 mod shortcuts;
+mod table;
+mod users;
 
 const WORKER_THREAD_POLL: Duration = Duration::from_secs(5);
-const LABEL_EXTRACTOR: &[u8; 2124] = include_bytes!("./extractor.dex");
+/// Built from extractor/Main.java by extractor/build.sh.
+const LABEL_EXTRACTOR: &[u8] = include_bytes!("./extractor.dex");
 
 type FrontendPayload = PackageDiff;
 
 struct App {
     search_query: String,
-    entries: BTreeMap<String, listview::Entry>,
     categories: u8,
 
-    package_diff_rx: Receiver<FrontendPayload>,
-    device_lost_rx: Receiver<()>,
-    action_tx: Sender<Action>,
-    action_error_rx: Receiver<ShellRunError>,
-    action_done_rx: Receiver<()>,
+    /// Connected devices keyed by serial number.
+    sessions: BTreeMap<String, device::Session>,
+    current_device: Option<String>,
+    session_rx: Receiver<device::Session>,
+    session_tx: Sender<device::Session>,
+    connect_dialog: Option<connect_dialog::ConnectDialog>,
 
+    pending_profile: Option<profile::Plan>,
+    pending_confirmation: Option<confirm::Confirmation>,
+    history: Option<journal::History>,
+    /// Commands the last actions would have run, shown instead of running them.
+    dry_run_plan: Option<Vec<String>>,
+    /// Problems not tied to an action, such as a profile that failed to load.
+    notices: Vec<String>,
     disable_mode: bool,
-    have_device: bool,
-    busy: bool,
+    dry_run: bool,
+
+    sort: table::Sort,
+    /// Whether backups also get copied to this computer, for every device.
+    pull_backups: bool,
+    /// The entry shown in the side panel, by package ID.
+    expanded: Option<String>,
+    /// The entry clicked last, where shift click ranges start.
+    selection_anchor: Option<String>,
+    /// The entry the keyboard acts on.
+    focused: Option<String>,
+    scroll_to_focus: bool,
 }
 
 type PackageIdentifier = String;
@@ -52,9 +94,15 @@ pub struct Package {
     id: PackageIdentifier,
     path: PackagePath,
     label: String,
+    /// On-disk size in KiB.
+    size: Option<u64>,
+    /// The version code, as the label extractor reads it.
+    version: Option<i64>,
 }
 
 struct PackageDiff {
+    /// The users this diff was computed for.
+    users: UserScope,
     added: Vec<Package>,
     removed: Vec<PackageIdentifier>,
     disabled: Vec<String>,
@@ -70,14 +118,28 @@ impl PackageDiff {
     }
 }
 
+#[derive(Debug)]
 pub enum ShellRunError {
     Timeout,
     ParseError,
     Unrecoverable,
-    UninstallFailed(PackageIdentifier),
+    UninstallFailed(PackageIdentifier, String),
     BackupNotPossible(PackageIdentifier),
-    RevertFailed(PackageIdentifier),
-    DisableFailed(PackageIdentifier),
+    RevertFailed(PackageIdentifier, String),
+    DisableFailed(PackageIdentifier, String),
+    Protected(PackageIdentifier, protected::Role),
+}
+
+impl ShellRunError {
+    /// What the device printed for the command that failed, if anything.
+    fn output(&self) -> Option<&str> {
+        match self {
+            ShellRunError::UninstallFailed(_, output)
+            | ShellRunError::RevertFailed(_, output)
+            | ShellRunError::DisableFailed(_, output) => Some(output.trim()),
+            _ => None,
+        }
+    }
 }
 
 impl Display for ShellRunError {
@@ -88,12 +150,15 @@ impl Display for ShellRunError {
                 f.write_str("failed to parse the output of shell command from the device")
             }
             ShellRunError::Unrecoverable => f.write_str("unrecoverable error"),
-            ShellRunError::UninstallFailed(id) => write!(f, "failed to uninstall package {id}"),
+            ShellRunError::UninstallFailed(id, _) => write!(f, "failed to uninstall package {id}"),
             ShellRunError::BackupNotPossible(id) => {
                 write!(f, "failed to backup package {id} before uninstall")
             }
-            ShellRunError::RevertFailed(id) => write!(f, "failed to revert package {id}"),
-            ShellRunError::DisableFailed(id) => write!(f, "failed to disable package {id}"),
+            ShellRunError::RevertFailed(id, _) => write!(f, "failed to revert package {id}"),
+            ShellRunError::DisableFailed(id, _) => write!(f, "failed to disable package {id}"),
+            ShellRunError::Protected(id, role) => {
+                write!(f, "refused to remove package {id}, it is {role}")
+            }
         }
     }
 }
@@ -104,10 +169,35 @@ pub struct Metadata {
     removal: u8,
 }
 
+/// Release builds on Windows start without a console, so the command line borrows the one of
+/// the shell it was run from to print anything.
+#[cfg(all(windows, not(debug_assertions)))]
+fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    // SAFETY: takes no pointers, and failing to attach only leaves the output unseen
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
 fn main() -> eframe::Result {
     env_logger::builder()
         .filter_level(log::LevelFilter::Warn)
         .init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        #[cfg(all(windows, not(debug_assertions)))]
+        attach_console();
+        std::process::exit(cli::run(&args));
+    }
+    lists::load(None);
+    overrides::load(None);
+
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_inner_size([800.0, 300.0]),
         ..Default::default()
@@ -117,193 +207,497 @@ fn main() -> eframe::Result {
         "Zilch",
         options,
         Box::new(|cc| {
-            let (package_diff_tx, package_diff_rx) = channel();
-            let (device_lost_tx, device_lost_rx) = channel();
-            let (action_tx, action_rx) = channel();
-            let (action_done_tx, action_done_rx) = channel();
-            let (action_result_tx, action_result_rx) = channel();
+            let (session_tx, session_rx) = channel();
 
+            egui_extras::install_image_loaders(&cc.egui_ctx);
             let ctx = cc.egui_ctx.clone();
-            spawn(move || {
-                worker_thread(
-                    package_diff_tx,
-                    device_lost_tx,
-                    action_rx,
-                    action_done_tx,
-                    action_result_tx,
-                    ctx,
-                )
-            });
+            let discovery_session_tx = session_tx.clone();
+            spawn(move || device::discovery_thread(discovery_session_tx, ctx));
 
             Ok(Box::new(App {
-                busy: false,
                 disable_mode: false,
-                have_device: false,
                 search_query: "".to_owned(),
-                device_lost_rx,
-                package_diff_rx,
-                entries: Default::default(),
-                action_tx,
+                sessions: Default::default(),
+                current_device: None,
+                session_rx,
+                session_tx,
+                connect_dialog: None,
                 categories: categories::RECOMMENDED,
-                action_done_rx,
-                action_error_rx: action_result_rx,
+                pending_profile: None,
+                pending_confirmation: None,
+                history: None,
+                dry_run_plan: None,
+                notices: vec![],
+                dry_run: false,
+                sort: cc
+                    .storage
+                    .and_then(|storage| eframe::get_value(storage, table::SORT_KEY))
+                    .unwrap_or_default(),
+                pull_backups: cc
+                    .storage
+                    .and_then(|storage| eframe::get_value(storage, host_store::PULL_BACKUPS_KEY))
+                    .unwrap_or_default(),
+                expanded: None,
+                selection_anchor: None,
+                focused: None,
+                scroll_to_focus: false,
             }))
         }),
     )
 }
 
+/// Why a worker stopped, which tells network connections whether to reconnect.
+#[derive(Debug, PartialEq, Eq)]
+enum WorkerExit {
+    /// The device could not be prepared, for instance because it is still booting.
+    Unprepared,
+    /// The device stopped answering after its session was handed to the frontend.
+    DeviceLost,
+    /// The frontend dropped the session, so nobody is interested in the device anymore. It
+    /// turns down sessions of a serial it already has a device for.
+    SessionDropped,
+}
+
 fn worker_thread(
-    package_diff_tx: Sender<PackageDiff>,
-    device_lost_tx: Sender<()>,
-    action_rx: Receiver<Action>,
-    action_done_tx: Sender<()>,
-    action_error_tx: Sender<ShellRunError>,
+    mut device: Box<dyn ADBDeviceExt>,
+    transport: device::Transport,
+    session_tx: Sender<device::Session>,
     ctx: egui::Context,
-) {
-    let mut maybe_device: Option<ADBUSBDevice> = None;
+) -> WorkerExit {
+    let prepared = prepare_device(device.as_mut()).and_then(|props| {
+        let info = device::DeviceInfo::read(device.as_mut(), props.serial.clone(), transport)?;
+        Ok((props, info))
+    });
+    let (mut props, info) = match prepared {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            log::error!("failed to prepare the device: {e}");
+            return WorkerExit::Unprepared;
+        }
+    };
+
+    let (package_diff_tx, package_diff_rx) = channel();
+    let (device_lost_tx, device_lost_rx) = channel();
+    let (action_tx, action_rx) = channel();
+    let (action_done_tx, action_done_rx) = channel();
+    let (action_error_tx, action_error_rx) = channel();
+    let (user_scope_tx, user_scope_rx) = channel();
+    let (pull_backups_tx, pull_backups_rx) = channel();
+    let (details_request_tx, details_request_rx) = channel();
+    let (details_tx, details_rx) = channel();
+    let (reboot_tx, reboot_rx) = channel();
+    let (health_tx, health_rx) = channel();
+    let (booted_tx, booted_rx) = channel();
+    let (crash_tx, crash_rx) = channel();
+    let (icon_tx, icon_rx) = channel();
+    let (notice_tx, notice_rx) = channel();
+
+    let session = device::Session {
+        info,
+        users: props.users.clone(),
+        user_scope: UserScope::default(),
+        user_scope_tx,
+        sdk_version: props.sdk_version,
+        pull_backups: props.pull_backups,
+        pull_backups_tx,
+        protected: props.protected.clone(),
+        entries: Default::default(),
+        package_diff_rx,
+        device_lost_rx,
+        action_tx,
+        action_error_rx,
+        action_done_rx,
+        details_tx: details_request_tx,
+        details_rx,
+        reboot_tx,
+        health_rx,
+        booted_rx,
+        health: None,
+        crash_rx,
+        crashes: vec![],
+        icon_rx,
+        icons: Default::default(),
+        notice_rx,
+        failures: vec![],
+        journaled: !journal::read(&props.serial).is_empty(),
+        have_packages: false,
+        busy: false,
+    };
+    if session_tx.send(session).is_err() {
+        return WorkerExit::SessionDropped;
+    }
+    ctx.request_repaint();
+
+    let report = |report| {
+        let _ = health_tx.send(report);
+        ctx.request_repaint();
+    };
+    // users and roles read while the device was still booting fall back to defaults
+    let resumed = health::resume(device.as_mut(), &props, report).and_then(|resumed| {
+        if resumed {
+            let users = users::list_users(device.as_mut())?;
+            let protected = protected::detect(device.as_mut())?;
+            props.users = users.clone();
+            props.protected = protected.clone();
+            let _ = booted_tx.send((users, protected));
+            ctx.request_repaint();
+        }
+        Ok(())
+    });
+    if resumed.is_err() {
+        let _ = device_lost_tx.send(());
+        ctx.request_repaint();
+        return WorkerExit::DeviceLost;
+    }
+
+    let mut crash_watch = crash_watch::Watch::start(device.as_mut())
+        .inspect_err(|e| log::warn!("not watching {} for crashes: {e}", props.serial))
+        .ok();
+
     let mut pkg_set: BTreeSet<PackageIdentifier> = Default::default();
     let mut disabled_set: BTreeSet<PackageIdentifier> = Default::default();
-    let mut device_version = 0;
+    let mut users = UserScope::default();
+    let mut package_watch = package_watch::Watch::default();
+    let mut icon_fetcher = icons::Fetcher::new(&props.serial);
+    let mut icons_failed = false;
+    let mut listing_failed = false;
 
     loop {
-        while maybe_device.is_none() {
-            maybe_device = ADBUSBDevice::autodetect().ok();
-            if maybe_device.is_none() {
-                sleep(WORKER_THREAD_POLL);
-            }
+        if let Ok(new_users) = user_scope_rx.try_recv() {
+            // the frontend starts over with an empty list for the new users
+            users = new_users;
+            pkg_set.clear();
+            disabled_set.clear();
+            package_watch.invalidate();
         }
 
-        if let Some(device) = maybe_device.as_mut() {
-            let Ok(sdk_version_str) = device.shell_command_text("getprop ro.build.version.sdk")
-            else {
-                log::error!("failed to get android version from the device");
-                maybe_device = None;
-                continue;
-            };
+        if let Ok(pull_backups) = pull_backups_rx.try_recv() {
+            props.pull_backups = pull_backups;
+        }
 
-            let Ok(sdk_version) = sdk_version_str.trim().parse::<u16>() else {
-                log::error!("failed to parse device android sdk version");
-                maybe_device = None;
-                continue;
-            };
+        // do all the actions in bulk before the next render
+        let mut batch = journal::Batch::new(&props.serial);
+        while let Ok(queued) = action_rx.try_recv() {
+            package_watch.invalidate();
+            props.allow_protected = queued.allow_protected;
+            for action in queued.actions {
+                let retry = action.clone();
+                let id = action.package_id();
+                let known = if disabled_set.contains(id) {
+                    listview::State::Disabled
+                } else if pkg_set.contains(id) {
+                    listview::State::Enabled
+                } else {
+                    listview::State::Uninstalled
+                };
+                if let Err(error) = batch.apply(action, device.as_mut(), &props, known) {
+                    let _ = action_error_tx.send(action::Failure {
+                        action: retry,
+                        error,
+                    });
+                }
+            }
+        }
+        props.allow_protected = false;
 
-            device_version = sdk_version;
+        while let Ok((id, path)) = details_request_rx.try_recv() {
+            let details =
+                details::read(device.as_mut(), &id, &path).map_err(|error| error.to_string());
+            let _ = details_tx.send((id, details));
+        }
 
-            let mut label_extractor_dex_stream = BufReader::new(&LABEL_EXTRACTOR[..]);
-            let remote_path = "/data/local/tmp/extractor.dex";
-            device
-                .push(&mut label_extractor_dex_stream, &remote_path)
-                .expect("failed to upload extractor to the device");
+        if reboot_rx.try_recv().is_ok() {
+            match health::reboot(device.as_mut(), &props) {
+                Ok(()) => report(health::Report::Rebooting),
+                Err(e) => report(health::Report::Failed(e)),
+            }
         }
 
-        while let Some(device) = maybe_device.as_mut() {
-            // do all the actions in bulk before the next render
-            while let Ok(action) = action_rx.try_recv() {
-                if let Err(action_error) = action.apply_on_device(device, device_version) {
-                    action_error_tx
-                        .send(action_error)
-                        .expect("failed to send to ui");
+        if let Some(watch) = crash_watch.as_mut() {
+            match watch.read(device.as_mut()) {
+                Ok(events) if !events.is_empty() => {
+                    let removals = crash_watch::recent_removals(&journal::read(&props.serial));
+                    let crashes = crash_watch::link(events, &removals);
+                    if !crashes.is_empty() && crash_tx.send(crashes).is_ok() {
+                        ctx.request_repaint();
+                    }
                 }
+                Ok(_) => {}
+                Err(e) => log::warn!("failed to read logcat of {}: {e}", props.serial),
             }
+        }
 
-            action_done_tx.send(()).expect("failed to send to ui");
-            match fetch_packages(device, &pkg_set, &disabled_set) {
-                Ok((diff, new_pkg_set, new_disabled_set)) => {
-                    if diff.same_as_before() {
-                        sleep(WORKER_THREAD_POLL);
-                        continue;
+        // the frontend drops a session whose serial another connection
+        // already uses, which ends this worker
+        if action_done_tx.send(()).is_err() {
+            return WorkerExit::SessionDropped;
+        }
+        if !icon_fetcher.is_idle() {
+            match icon_fetcher.fetch_batch(device.as_mut()) {
+                Ok(icons) => {
+                    if !icons.is_empty() && icon_tx.send(icons).is_ok() {
+                        ctx.request_repaint();
                     }
-                    pkg_set = new_pkg_set;
-                    disabled_set = new_disabled_set;
-                    package_diff_tx.send(diff).expect("failed to send to ui");
                 }
-                Err(ShellRunError::Timeout) => {}
-                Err(_log_this_later) => {
-                    maybe_device = None;
-                    pkg_set = BTreeSet::default();
-                    device_lost_tx.send(()).expect("failed to send to ui");
+                Err(e) => {
+                    log::warn!("failed to fetch icons from {}: {e}", props.serial);
+                    // the next batches likely fail the same way, once is enough to tell
+                    if !icons_failed {
+                        icons_failed = true;
+                        let _ = notice_tx.send(format!(
+                            "icons of {} could not be rendered on it: {e}",
+                            props.serial
+                        ));
+                        ctx.request_repaint();
+                    }
                 }
             }
-            ctx.request_repaint();
-
-            sleep(WORKER_THREAD_POLL);
         }
+        match package_watch.check(device.as_mut()) {
+            Ok(true) => {}
+            Ok(false) | Err(ShellRunError::Timeout) => {
+                sleep(package_watch::POLL);
+                continue;
+            }
+            Err(_log_this_later) => {
+                let _ = device_lost_tx.send(());
+                ctx.request_repaint();
+                return WorkerExit::DeviceLost;
+            }
+        }
+        match fetch_packages(device.as_mut(), &props, users, &pkg_set, &disabled_set) {
+            Ok((diff, new_pkg_set, new_disabled_set)) => {
+                listing_failed = false;
+                if diff.same_as_before() {
+                    sleep(package_watch::POLL);
+                    continue;
+                }
+                pkg_set = new_pkg_set;
+                disabled_set = new_disabled_set;
+                let added: Vec<(PackageIdentifier, Option<i64>)> = diff
+                    .added
+                    .iter()
+                    .map(|package| (package.id.clone(), package.version))
+                    .collect();
+                if package_diff_tx.send(diff).is_err() {
+                    return WorkerExit::SessionDropped;
+                }
+                // the list shows up first, icons not cached yet follow a batch at a time
+                let cached = icon_fetcher.request(added);
+                if !cached.is_empty() {
+                    let _ = icon_tx.send(cached);
+                }
+            }
+            Err(ShellRunError::Timeout) => package_watch.invalidate(),
+            // the device answered, just not in a way zilch understood, so the packages are
+            // listed again at the next poll rather than shown without their labels
+            Err(e @ ShellRunError::ParseError) => {
+                log::error!("failed to list the packages of {}: {e}", props.serial);
+                package_watch.invalidate();
+                if !listing_failed {
+                    listing_failed = true;
+                    let _ = notice_tx.send(format!(
+                        "the packages of {} could not be listed, zilch keeps trying: {e}",
+                        props.serial
+                    ));
+                }
+            }
+            Err(_log_this_later) => {
+                let _ = device_lost_tx.send(());
+                ctx.request_repaint();
+                return WorkerExit::DeviceLost;
+            }
+        }
+        ctx.request_repaint();
+
+        sleep(package_watch::POLL);
+    }
+}
+
+/// Where zilch keeps its files on this computer.
+fn data_dir() -> Option<std::path::PathBuf> {
+    eframe::storage_dir("Zilch")
+}
+
+fn reconcile(entries: &mut BTreeMap<String, listview::Entry>, package_diff: PackageDiff) {
+    for package in package_diff.added {
+        let found = lists::lookup(&package.id);
+        let overrides = overrides::lookup(&package.id);
+        entries.insert(
+            package.id.clone(),
+            listview::Entry {
+                package,
+                metadata: found.map(|(metadata, _)| metadata),
+                source: found.map(|(_, source)| source),
+                overrides,
+                details: None,
+                details_requested: false,
+                state: listview::State::Enabled,
+                selected: false,
+            },
+        );
+    }
+
+    for package_id in package_diff.removed {
+        if let Some(entry) = entries.get_mut(&package_id) {
+            entry.state = listview::State::Uninstalled;
+        };
+    }
+
+    for package_id in package_diff.disabled {
+        if let Some(entry) = entries.get_mut(&package_id) {
+            entry.state = listview::State::Disabled;
+        };
+    }
+
+    for package_id in package_diff.re_enabled {
+        if let Some(entry) = entries.get_mut(&package_id) {
+            entry.state = listview::State::Enabled;
+        };
     }
 }
 
 impl App {
-    fn reconcile(&mut self, package_diff: PackageDiff) {
-        for package in package_diff.added {
-            let maybe_meta = metadata::STORE.get(&package.id);
-            self.entries.insert(
-                package.id.clone(),
-                listview::Entry {
-                    package,
-                    metadata: maybe_meta,
-                    expand_triggered: false,
-                    state: listview::State::Enabled,
-                    selected: false,
-                },
-            );
-        }
+    fn session(&self) -> Option<&device::Session> {
+        self.sessions.get(self.current_device.as_ref()?)
+    }
 
-        for package_id in package_diff.removed {
-            if let Some(entry) = self.entries.get_mut(&package_id) {
-                entry.state = listview::State::Uninstalled;
-            };
-        }
+    fn session_mut(&mut self) -> Option<&mut device::Session> {
+        self.sessions.get_mut(self.current_device.as_ref()?)
+    }
 
-        for package_id in package_diff.disabled {
-            if let Some(entry) = self.entries.get_mut(&package_id) {
-                entry.state = listview::State::Disabled;
-            };
-        }
+    fn device_picker(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Device");
+            let selected = self.session().map(|s| s.info.name()).unwrap_or_default();
+            egui::ComboBox::from_id_salt("device_picker")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for (serial, session) in self.sessions.iter() {
+                        ui.selectable_value(
+                            &mut self.current_device,
+                            Some(serial.clone()),
+                            session.info.name(),
+                        );
+                    }
+                });
+            if ui.button("connect…").clicked() {
+                self.connect_dialog = Some(connect_dialog::ConnectDialog::new(ui.ctx()));
+            }
 
-        for package_id in package_diff.re_enabled {
-            if let Some(entry) = self.entries.get_mut(&package_id) {
-                entry.state = listview::State::Enabled;
-            };
-        }
+            if let Some(session) = self.session_mut()
+                && session.users.len() > 1
+            {
+                ui.separator();
+                ui.label("User");
+                let mut user_scope = session.user_scope;
+                egui::ComboBox::from_id_salt("user_picker")
+                    .selected_text(session.user_name(user_scope))
+                    .show_ui(ui, |ui| {
+                        for user in session.users.iter() {
+                            let scope = UserScope::User(user.id);
+                            ui.selectable_value(&mut user_scope, scope, session.user_name(scope));
+                        }
+                        ui.selectable_value(
+                            &mut user_scope,
+                            UserScope::All,
+                            session.user_name(UserScope::All),
+                        );
+                    });
+                session.set_user_scope(user_scope);
+            }
+        });
     }
 }
 
 impl eframe::App for App {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, table::SORT_KEY, &self.sort);
+        eframe::set_value(storage, host_store::PULL_BACKUPS_KEY, &self.pull_backups);
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         ctx.set_pixels_per_point(1.5);
 
-        if let Ok(()) = self.action_done_rx.try_recv() {
-            self.busy = false;
+        while let Ok(mut session) = self.session_rx.try_recv() {
+            // devices sharing a serial would share their list, journal and backups, so the
+            // one that came later is left alone, unless the other one is gone already
+            if let Some(existing) = self.sessions.get_mut(&session.info.serial)
+                && existing.poll()
+            {
+                self.notices.push(format!(
+                    "{} ({}) was left alone, {} ({}) already uses its serial {:?}",
+                    session.info.model,
+                    session.info.transport,
+                    existing.info.model,
+                    existing.info.transport,
+                    session.info.serial
+                ));
+                continue;
+            }
+            if self.pull_backups {
+                session.pull_backups = true;
+                let _ = session.pull_backups_tx.send(true);
+            }
+            self.sessions.insert(session.info.serial.clone(), session);
         }
 
-        if let Ok(action_error) = self.action_error_rx.try_recv() {
-            log::error!("{}", action_error.to_string());
+        for session in self.sessions.values() {
+            self.notices.extend(session.notice_rx.try_iter());
         }
+        self.sessions.retain(|_, session| session.poll());
 
-        if let Ok(package_diff) = self.package_diff_rx.try_recv() {
-            self.have_device = true;
-            self.reconcile(package_diff);
+        if self
+            .current_device
+            .as_ref()
+            .is_none_or(|serial| !self.sessions.contains_key(serial))
+        {
+            self.current_device = self.sessions.keys().next().cloned();
         }
 
-        if let Ok(()) = self.device_lost_rx.try_recv() {
-            self.have_device = false;
-            self.entries.clear();
-
-            log::warn!("device lost");
-        }
+        self.connect_dialog(ctx);
 
-        if !self.have_device {
+        let Some(session) = self.session() else {
             egui::CentralPanel::default().show(ctx, |ui| {
                 center_horizontal(ui, |ui| {
                     column(ui, Align::Center, |ui| {
                     ui.add(Spinner::new());
                     ui.add(Label::new("Waiting for a device.\nPlease connect your Android device via USB ensuring\nthat USB debugging is enabled in developer settings."));
+                    if ui.button("connect over the network").clicked() {
+                        self.connect_dialog = Some(connect_dialog::ConnectDialog::new(ui.ctx()));
+                    }
                     });
                 });
             });
             return;
         };
 
+        let have_packages = session.have_packages;
+        TopBottomPanel::top("device_picker").show(ctx, |ui| self.device_picker(ui));
+        self.health_panel(ctx);
+
+        if !have_packages {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                center_horizontal(ui, |ui| {
+                    column(ui, Align::Center, |ui| {
+                        ui.add(Spinner::new());
+                        ui.add(Label::new("Reading packages from the device."));
+                    });
+                });
+            });
+            return;
+        }
+
+        self.profile_preview(ctx);
+        // undoing from the history asks first, on top of it
+        self.history_view(ctx);
+        self.confirmation_dialog(ctx);
+        self.dry_run_preview(ctx);
+
         TopBottomPanel::bottom("action_bar").show(ctx, |ui| self.action_bar(ui));
+        self.error_panel(ctx);
+        self.crash_panel(ctx);
+        self.entry_panel(ctx);
 
         CentralPanel::default().show(ctx, |ui| {
             ui.take_available_width();
@@ -316,62 +710,105 @@ impl eframe::App for App {
             });
 
             ui.separator();
-            egui::ScrollArea::vertical().show(ui, |ui| {
-                for (id, entry) in self.entries.iter_mut() {
-                    let query_lower = self.search_query.to_lowercase();
-                    let entry_removal = entry
-                        .metadata
-                        .map(|m| m.removal)
-                        .unwrap_or(categories::UNIDENTIFIED);
-                    if (id.to_lowercase().contains(&query_lower)
-                        || entry.package.label.to_lowercase().contains(&query_lower))
-                        && (entry_removal & self.categories == entry_removal)
-                    {
-                        entry.render(ui);
-                    }
-                }
-            });
+            self.package_table(ui);
             self.handle_shortcuts(ui, search.response);
         });
     }
 }
 
-fn fetch_packages(
-    device: &mut ADBUSBDevice,
+/// Reads what actions need to know about the device and uploads the label extractor.
+fn prepare_device<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+) -> Result<device::DeviceProps, ShellRunError> {
+    let sdk_version = device
+        .shell_command_text("getprop ro.build.version.sdk")?
+        .trim()
+        .parse::<u16>()
+        .map_err(|_| ShellRunError::ParseError)?;
+
+    let mut label_extractor_dex_stream = BufReader::new(LABEL_EXTRACTOR);
+    let remote_path = "/data/local/tmp/extractor.dex";
+    device
+        .push_file(&mut label_extractor_dex_stream, remote_path)
+        .map_err(|_| ShellRunError::Unrecoverable)?;
+
+    let serial = device
+        .shell_command_text("getprop ro.serialno")?
+        .trim()
+        .to_string();
+
+    Ok(device::DeviceProps {
+        serial,
+        sdk_version,
+        users: users::list_users(device)?,
+        pull_backups: false,
+        protected: protected::detect(device)?,
+        allow_protected: false,
+    })
+}
+
+fn fetch_packages<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+    props: &device::DeviceProps,
+    users: UserScope,
     pkg_set: &BTreeSet<String>,
     disabled_set: &BTreeSet<String>,
 ) -> Result<(PackageDiff, BTreeSet<String>, BTreeSet<String>), ShellRunError> {
-    let raw_pkg_text = device.shell_command_text("pm list packages -f")?;
-
     let mut current_set = BTreeSet::new();
+    let mut current_disabled_set = BTreeSet::new();
+    // a package only counts as disabled if no user in scope has it enabled
+    let mut enabled_somewhere = BTreeSet::new();
+
+    // pm prints version codes since Android 9, dumpsys has them before that
+    let show_versions = props.sdk_version >= 28;
+    let options = if show_versions {
+        " --show-versioncode"
+    } else {
+        ""
+    };
 
     let mut new_packages = BTreeMap::new();
-    for line in raw_pkg_text.lines() {
-        let stripped = line.strip_prefix("package:").unwrap_or(line);
-        let (path, id) = stripped.rsplit_once("=").unwrap_or((line, ""));
-        current_set.insert(id.to_string());
-
-        if !pkg_set.contains(id) {
-            let package = Package {
-                path: path.to_string(),
-                id: id.to_string(),
-                label: String::default(),
+    for user in users.ids(&props.users) {
+        let raw_pkg_text =
+            device.shell_command_text(&format!("pm list packages -f{options} --user {user}"))?;
+        let mut user_set = BTreeSet::new();
+        for line in raw_pkg_text.lines() {
+            let stripped = line.strip_prefix("package:").unwrap_or(line);
+            let (path, id) = stripped.rsplit_once("=").unwrap_or((line, ""));
+            let (id, version) = match id.split_once(" versionCode:") {
+                Some((id, version)) => (id, version.trim().parse().ok()),
+                None => (id, None),
             };
+            current_set.insert(id.to_string());
+            user_set.insert(id.to_string());
+
+            if !pkg_set.contains(id) && !new_packages.contains_key(id) {
+                let package = Package {
+                    path: path.to_string(),
+                    id: id.to_string(),
+                    label: String::default(),
+                    size: None,
+                    version,
+                };
+
+                new_packages.insert(id.to_string(), package);
+            }
+        }
 
-            new_packages.insert(id, package);
+        // disabled
+        let raw_pkg_text =
+            device.shell_command_text(&format!("pm list packages -d --user {user}"))?;
+        for line in raw_pkg_text.lines() {
+            let id = line.strip_prefix("package:").unwrap_or(line);
+            user_set.remove(id);
+            current_disabled_set.insert(id.to_string());
         }
+        enabled_somewhere.extend(user_set);
     }
+    current_disabled_set.retain(|id| !enabled_somewhere.contains(id));
 
     let removed = pkg_set.difference(&current_set).cloned().collect();
 
-    // disabled
-    let raw_pkg_text = device.shell_command_text("pm list packages -d")?;
-    let mut current_disabled_set = BTreeSet::new();
-    for line in raw_pkg_text.lines() {
-        let id = line.strip_prefix("package:").unwrap_or(line);
-        current_disabled_set.insert(id.to_string());
-    }
-
     let re_enabled = disabled_set
         .difference(&current_disabled_set)
         .map(|v| v.to_string())
@@ -387,21 +824,36 @@ fn fetch_packages(
         let raw_pkg_text = device
             .shell_command_text("CLASSPATH=/data/local/tmp/extractor.dex app_process / Main")?;
 
-        for line in raw_pkg_text.lines() {
-            let mut splitn = line.splitn(3, ' ');
-            // discard
-            splitn.next();
-            let id = splitn.next().expect("split n=3 does not have 3 elements");
-            let label = splitn.next().expect("split n=3 does not have 3 elements");
-
-            if let Some(package_mut) = new_packages.get_mut(id) {
-                package_mut.label = label.to_string();
+        for app in extractor::apps(&raw_pkg_text)? {
+            if let Some(package_mut) = new_packages.get_mut(&app.id) {
+                package_mut.label = app.label;
             }
         }
+
+        if !show_versions {
+            let dumpsys = device.shell_command_text("dumpsys package packages")?;
+            for (id, version) in details::version_codes(&dumpsys) {
+                if let Some(package_mut) = new_packages.get_mut(&id) {
+                    package_mut.version = Some(version);
+                }
+            }
+        }
+    }
+
+    if !new_packages.is_empty() {
+        let paths: Vec<(&str, &str)> = new_packages
+            .values()
+            .map(|package| (package.id.as_str(), package.path.as_str()))
+            .collect();
+        let sizes = details::sizes(device, &paths)?;
+        for (id, package) in new_packages.iter_mut() {
+            package.size = sizes.get(id).copied();
+        }
     }
 
     Ok((
         PackageDiff {
+            users,
             added: new_packages.into_values().collect(),
             removed,
             disabled,
@@ -411,3 +863,197 @@ fn fetch_packages(
         current_disabled_set,
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_device::{FakeDevice, Reply};
+    use crate::listview::State;
+
+    fn phone() -> FakeDevice {
+        FakeDevice::new(34)
+            .with_user(10, "Work profile")
+            .with_package("com.example.bloat", "Bloat")
+            .with_package("com.example.keep", "Keep")
+    }
+
+    /// Fetches packages the way the worker does and feeds the diff to `entries`.
+    fn refresh(
+        device: &mut FakeDevice,
+        props: &device::DeviceProps,
+        users: UserScope,
+        sets: &mut (BTreeSet<String>, BTreeSet<String>),
+        entries: &mut BTreeMap<String, listview::Entry>,
+    ) -> bool {
+        let (diff, pkg_set, disabled_set) =
+            fetch_packages(device, props, users, &sets.0, &sets.1).unwrap();
+        let changed = !diff.same_as_before();
+        *sets = (pkg_set, disabled_set);
+        reconcile(entries, diff);
+        changed
+    }
+
+    #[test]
+    fn prepare_device_reads_props_and_pushes_extractor() {
+        let mut device = phone();
+        let props = prepare_device(&mut device).unwrap();
+
+        assert_eq!(props.serial, "FAKE0001");
+        assert_eq!(props.sdk_version, 34);
+        let users: Vec<u32> = props.users.iter().map(|user| user.id).collect();
+        assert_eq!(users, [0, 10]);
+        assert_eq!(
+            device.files["/data/local/tmp/extractor.dex"],
+            LABEL_EXTRACTOR
+        );
+    }
+
+    #[test]
+    fn fetch_lists_packages_with_labels() {
+        let mut device = phone();
+        let props = prepare_device(&mut device).unwrap();
+        let mut sets = Default::default();
+        let mut entries = BTreeMap::new();
+
+        assert!(refresh(
+            &mut device,
+            &props,
+            UserScope::default(),
+            &mut sets,
+            &mut entries
+        ));
+        assert_eq!(entries.len(), 2);
+        let bloat = &entries["com.example.bloat"];
+        assert_eq!(bloat.package.label, "Bloat");
+        assert_eq!(bloat.package.version, Some(1));
+        assert_eq!(bloat.package.path, "/data/app/com.example.bloat/base.apk");
+        assert!(bloat.state == State::Enabled);
+    }
+
+    #[test]
+    fn fetch_reads_version_codes_before_android_9() {
+        let mut device = FakeDevice::new(26).with_package("com.example.bloat", "Bloat");
+        let props = prepare_device(&mut device).unwrap();
+        let mut sets = Default::default();
+        let mut entries = BTreeMap::new();
+
+        refresh(
+            &mut device,
+            &props,
+            UserScope::default(),
+            &mut sets,
+            &mut entries,
+        );
+        assert_eq!(entries["com.example.bloat"].package.version, Some(1));
+        assert!(
+            device
+                .commands
+                .contains(&"pm list packages -f --user 0".to_string())
+        );
+    }
+
+    #[test]
+    fn fetch_only_reports_changes() {
+        let mut device = phone();
+        let props = prepare_device(&mut device).unwrap();
+        let mut sets = Default::default();
+        let mut entries = BTreeMap::new();
+        refresh(
+            &mut device,
+            &props,
+            UserScope::default(),
+            &mut sets,
+            &mut entries,
+        );
+        assert!(!refresh(
+            &mut device,
+            &props,
+            UserScope::default(),
+            &mut sets,
+            &mut entries
+        ));
+
+        device
+            .shell_command_text("pm disable-user --user 0 com.example.keep")
+            .unwrap();
+        device
+            .shell_command_text("pm uninstall --user 0 -k com.example.bloat")
+            .unwrap();
+        let (diff, _, _) =
+            fetch_packages(&mut device, &props, UserScope::default(), &sets.0, &sets.1).unwrap();
+        assert!(diff.added.is_empty());
+        assert_eq!(diff.removed, ["com.example.bloat"]);
+        assert_eq!(diff.disabled, ["com.example.keep"]);
+        assert!(diff.re_enabled.is_empty());
+
+        reconcile(&mut entries, diff);
+        assert!(entries["com.example.bloat"].state == State::Uninstalled);
+        assert!(entries["com.example.keep"].state == State::Disabled);
+    }
+
+    #[test]
+    fn disabled_for_one_user_is_still_enabled_for_all() {
+        let mut device = phone();
+        let props = prepare_device(&mut device).unwrap();
+        device
+            .shell_command_text("pm disable-user --user 10 com.example.keep")
+            .unwrap();
+
+        let mut sets = Default::default();
+        let mut entries = BTreeMap::new();
+        refresh(&mut device, &props, UserScope::All, &mut sets, &mut entries);
+        assert!(entries["com.example.keep"].state == State::Enabled);
+
+        let mut sets = Default::default();
+        let mut entries = BTreeMap::new();
+        refresh(
+            &mut device,
+            &props,
+            UserScope::User(10),
+            &mut sets,
+            &mut entries,
+        );
+        assert!(entries["com.example.keep"].state == State::Disabled);
+    }
+
+    #[test]
+    fn fetch_lists_packages_again_when_labels_fail() {
+        let mut device = phone();
+        let props = prepare_device(&mut device).unwrap();
+        device.script(
+            "CLASSPATH=",
+            Reply::Print("10000 com.example.bloat Bloat\n".to_string()),
+        );
+        let mut sets: (BTreeSet<String>, BTreeSet<String>) = Default::default();
+        let mut entries = BTreeMap::new();
+
+        let failed = fetch_packages(&mut device, &props, UserScope::default(), &sets.0, &sets.1);
+        assert!(matches!(failed, Err(ShellRunError::ParseError)));
+
+        device.unscript("CLASSPATH=");
+        refresh(
+            &mut device,
+            &props,
+            UserScope::default(),
+            &mut sets,
+            &mut entries,
+        );
+        assert_eq!(entries["com.example.bloat"].package.label, "Bloat");
+    }
+
+    #[test]
+    fn fetch_passes_timeouts_on() {
+        let mut device = phone();
+        let props = prepare_device(&mut device).unwrap();
+        device.script("pm list packages", Reply::Timeout);
+
+        let result = fetch_packages(
+            &mut device,
+            &props,
+            UserScope::default(),
+            &Default::default(),
+            &Default::default(),
+        );
+        assert!(matches!(result, Err(ShellRunError::Timeout)));
+    }
+}
@@ -0,0 +1,101 @@
+use crate::Action;
+
+use egui::{Color32, RichText, ScrollArea, TopBottomPanel};
+
+impl crate::App {
+    /// Lists the actions the device refused, with what it printed and a way to retry them,
+    /// along with any other problems zilch ran into.
+    pub fn error_panel(&mut self, ctx: &egui::Context) {
+        let dry_run = self.dry_run;
+        let notices = &mut self.notices;
+        let Some(session) = self
+            .current_device
+            .as_ref()
+            .and_then(|serial| self.sessions.get_mut(serial))
+        else {
+            return;
+        };
+        if session.failures.is_empty() && notices.is_empty() {
+            return;
+        }
+
+        let mut dismiss_notice = None;
+        let mut retry = vec![];
+        let mut dismiss = vec![];
+        let mut clear = false;
+        TopBottomPanel::bottom("error_panel")
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.add_space(4.0);
+                for (index, notice) in notices.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui.small_button("dismiss").clicked() {
+                            dismiss_notice = Some(index);
+                        }
+                        ui.label(RichText::new(notice).color(Color32::LIGHT_RED).size(12.0));
+                    });
+                }
+                if session.failures.is_empty() {
+                    ui.add_space(4.0);
+                    return;
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label(
+                        RichText::new(format!("{} failed actions", session.failures.len()))
+                            .color(Color32::LIGHT_RED),
+                    );
+                    if ui.button("retry all").clicked() {
+                        retry.extend(0..session.failures.len());
+                    }
+                    if ui.button("clear").clicked() {
+                        clear = true;
+                    }
+                });
+
+                ScrollArea::vertical().max_height(150.0).show(ui, |ui| {
+                    for (index, failure) in session.failures.iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            if ui.small_button("retry").clicked() {
+                                retry.push(index);
+                            }
+                            if ui.small_button("dismiss").clicked() {
+                                dismiss.push(index);
+                            }
+                            ui.label(RichText::new(failure.error.to_string()).size(12.0));
+                        });
+                        if let Some(output) = failure.error.output()
+                            && !output.is_empty()
+                        {
+                            ui.label(RichText::new(output).monospace().size(10.0));
+                        }
+                    }
+                });
+                ui.add_space(4.0);
+            });
+
+        if let Some(index) = dismiss_notice {
+            notices.remove(index);
+        }
+        let actions: Vec<Action> = retry
+            .iter()
+            .map(|&index| session.failures[index].action.clone())
+            .collect();
+
+        if clear {
+            session.failures.clear();
+        } else {
+            // retried actions report again if they fail a second time, and
+            // stay in the list when only their commands are shown
+            if !dry_run {
+                dismiss.extend(retry);
+            }
+            dismiss.sort_unstable();
+            dismiss.dedup();
+            for index in dismiss.into_iter().rev() {
+                session.failures.remove(index);
+            }
+        }
+        self.queue_actions(actions);
+    }
+}
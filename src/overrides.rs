@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use crate::{categories, lists};
+
+const FILE_NAME: &str = "zilch_overrides.json";
+const PATH_VARIABLE: &str = "ZILCH_OVERRIDES";
+
+/// A team's own take on a package, which wins over what the package lists say.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Override {
+    /// One of the category names, such as `Recommended`.
+    removal: Option<String>,
+    pub description: Option<String>,
+    pub note: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl Override {
+    pub fn removal(&self) -> Option<u8> {
+        categories::name_to_value(self.removal.as_deref()?)
+    }
+}
+
+struct Loaded {
+    path: PathBuf,
+    packages: HashMap<String, Override>,
+}
+
+static LOADED: OnceLock<Option<Loaded>> = OnceLock::new();
+
+fn parse(text: &str) -> Result<HashMap<String, Override>, String> {
+    let packages: HashMap<String, Override> =
+        serde_json::from_str(text).map_err(|e| e.to_string())?;
+    for (id, package) in packages.iter() {
+        if let Some(removal) = package.removal.as_ref()
+            && package.removal().is_none()
+        {
+            return Err(format!("unknown category {removal} for {id}"));
+        }
+    }
+    Ok(packages)
+}
+
+/// Loads the first overrides file found, looked for like package lists are.
+/// Only the first call does anything.
+pub fn load(path: Option<&Path>) {
+    LOADED.get_or_init(|| {
+        for path in lists::candidates(path, PATH_VARIABLE, FILE_NAME) {
+            if !path.is_file() {
+                continue;
+            }
+            let parsed = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| parse(&text));
+            match parsed {
+                Ok(packages) => return Some(Loaded { path, packages }),
+                Err(e) => log::warn!("ignoring overrides {}: {e}", path.display()),
+            }
+        }
+        None
+    });
+}
+
+pub fn lookup(id: &str) -> Option<&'static Override> {
+    LOADED.get()?.as_ref()?.packages.get(id)
+}
+
+/// The overrides file in use, if any.
+pub fn path() -> Option<&'static Path> {
+    Some(LOADED.get()?.as_ref()?.path.as_path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_overrides() {
+        let packages = parse(
+            r#"{
+                "com.carrier.app": {
+                    "removal": "Recommended",
+                    "note": "nobody here uses it",
+                    "tags": ["carrier"]
+                },
+                "com.example.keep": { "description": "Needed by our MDM" }
+            }"#,
+        )
+        .unwrap();
+
+        let carrier = &packages["com.carrier.app"];
+        assert_eq!(carrier.removal(), Some(categories::RECOMMENDED));
+        assert_eq!(carrier.note.as_deref(), Some("nobody here uses it"));
+        assert_eq!(carrier.tags, ["carrier"]);
+
+        let keep = &packages["com.example.keep"];
+        assert_eq!(keep.removal(), None);
+        assert_eq!(keep.description.as_deref(), Some("Needed by our MDM"));
+        assert!(keep.tags.is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_categories() {
+        assert!(parse(r#"{ "com.carrier.app": { "removal": "Optional" } }"#).is_err());
+    }
+}
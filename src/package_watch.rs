@@ -0,0 +1,93 @@
+use std::time::{Duration, Instant};
+
+use crate::adb_shell_text::AndroidDevice;
+use crate::{ShellRunError, WORKER_THREAD_POLL};
+
+/// How often the worker asks the device whether its packages changed.
+pub const POLL: Duration = Duration::from_secs(1);
+/// Packages are listed in full this often even if the device reports no change.
+const FULL_REFRESH: Duration = Duration::from_secs(60);
+
+/// The sequence number Android bumps on every package added, removed or changed, which
+/// `dumpsys package changes` prints since Android 8. Older devices print nothing useful.
+pub fn sequence_number<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+) -> Result<Option<u64>, ShellRunError> {
+    let output = device.shell_command_text("dumpsys package changes")?;
+    Ok(output.lines().find_map(|line| {
+        line.trim()
+            .strip_prefix("Sequence number=")?
+            .parse::<u64>()
+            .ok()
+    }))
+}
+
+/// Tells the worker when listing packages is worth it, instead of listing them every poll.
+#[derive(Default)]
+pub struct Watch {
+    sequence: Option<u64>,
+    /// When packages were last listed, none if they have to be listed again.
+    listed: Option<Instant>,
+}
+
+impl Watch {
+    /// Whether the packages may have changed since they were last listed. If so, they count as
+    /// listed from now on.
+    pub fn check<D: AndroidDevice + ?Sized>(
+        &mut self,
+        device: &mut D,
+    ) -> Result<bool, ShellRunError> {
+        let sequence = sequence_number(device)?;
+        // without a sequence number there is nothing to go by but time
+        let due = match sequence {
+            Some(_) => FULL_REFRESH,
+            None => WORKER_THREAD_POLL,
+        };
+        let changed =
+            sequence != self.sequence || self.listed.is_none_or(|listed| listed.elapsed() >= due);
+        if changed {
+            self.sequence = sequence;
+            self.listed = Some(Instant::now());
+        }
+        Ok(changed)
+    }
+
+    /// Has the packages listed at the next check, such as after actions or a failed listing.
+    pub fn invalidate(&mut self) {
+        self.listed = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_device::{FakeDevice, Reply};
+
+    #[test]
+    fn follows_the_sequence_number() {
+        let mut device = FakeDevice::new(34).with_package("com.example.bloat", "Bloat");
+        let mut watch = Watch::default();
+        assert!(watch.check(&mut device).unwrap());
+        assert!(!watch.check(&mut device).unwrap());
+
+        device
+            .shell_command_text("pm disable-user --user 0 com.example.bloat")
+            .unwrap();
+        assert!(watch.check(&mut device).unwrap());
+        assert!(!watch.check(&mut device).unwrap());
+
+        watch.invalidate();
+        assert!(watch.check(&mut device).unwrap());
+    }
+
+    #[test]
+    fn falls_back_to_polling_on_old_devices() {
+        let mut device = FakeDevice::new(25);
+        device.script("dumpsys package changes", Reply::Print(String::default()));
+        assert_eq!(sequence_number(&mut device).unwrap(), None);
+
+        let mut watch = Watch::default();
+        assert!(watch.check(&mut device).unwrap());
+        assert!(!watch.check(&mut device).unwrap());
+    }
+}
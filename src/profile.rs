@@ -0,0 +1,342 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+
+use egui::{Id, Modal, RichText, ScrollArea};
+
+use crate::listview::{Entry, State};
+use crate::users::UserScope;
+use crate::{Action, Package};
+
+#[derive(Default)]
+pub struct Profile {
+    enabled: BTreeSet<String>,
+    disabled: BTreeSet<String>,
+    uninstalled: BTreeSet<String>,
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+    MissingSeparator(usize),
+    UnknownKey(String),
+}
+
+impl Display for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileError::MissingSeparator(line) => {
+                write!(f, "line {line} is not of the form key=value")
+            }
+            ProfileError::UnknownKey(key) => write!(f, "unknown key {key}"),
+        }
+    }
+}
+
+pub struct Change {
+    pub id: String,
+    pub from: State,
+    pub to: State,
+}
+
+/// The changes needed to bring a device in line with a saved profile.
+pub struct Plan {
+    /// The device the plan was made for.
+    pub serial: String,
+    pub changes: Vec<Change>,
+    /// Packages mentioned in the profile that the device does not have.
+    pub missing: usize,
+}
+
+impl Profile {
+    pub fn from_entries(entries: &BTreeMap<String, Entry>) -> Self {
+        let mut profile = Profile::default();
+        for (id, entry) in entries.iter() {
+            let set = match entry.state {
+                State::Enabled => &mut profile.enabled,
+                State::Disabled => &mut profile.disabled,
+                State::Uninstalled => &mut profile.uninstalled,
+            };
+            set.insert(id.clone());
+        }
+        profile
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ProfileError> {
+        let mut profile = Profile::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(ProfileError::MissingSeparator(index + 1))?;
+            let set = match key.trim() {
+                "enabled" => &mut profile.enabled,
+                "disabled" => &mut profile.disabled,
+                "uninstalled" => &mut profile.uninstalled,
+                unknown => return Err(ProfileError::UnknownKey(unknown.to_string())),
+            };
+            set.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_string),
+            );
+        }
+        Ok(profile)
+    }
+
+    fn wanted_states(&self) -> impl Iterator<Item = (&String, State)> {
+        let enabled = self.enabled.iter().map(|id| (id, State::Enabled));
+        let disabled = self.disabled.iter().map(|id| (id, State::Disabled));
+        let uninstalled = self.uninstalled.iter().map(|id| (id, State::Uninstalled));
+        enabled.chain(disabled).chain(uninstalled)
+    }
+
+    pub fn diff(&self, serial: &str, entries: &BTreeMap<String, Entry>) -> Plan {
+        let mut changes = vec![];
+        let mut missing = 0;
+        for (id, to) in self.wanted_states() {
+            let Some(entry) = entries.get(id) else {
+                missing += 1;
+                continue;
+            };
+            if entry.state != to {
+                changes.push(Change {
+                    id: id.clone(),
+                    from: entry.state,
+                    to,
+                });
+            }
+        }
+        Plan {
+            serial: serial.to_string(),
+            changes,
+            missing,
+        }
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |set: &BTreeSet<String>| set.iter().cloned().collect::<Vec<_>>().join(",");
+        write!(
+            f,
+            "disabled={}\nenabled={}\nuninstalled={}",
+            join(&self.disabled),
+            join(&self.enabled),
+            join(&self.uninstalled),
+        )
+    }
+}
+
+impl Change {
+    /// Actions to queue on the worker, in order, to carry out this change.
+    pub fn actions(&self, package: &Package, users: UserScope) -> Vec<Action> {
+        match (self.from, self.to) {
+            (from, State::Enabled) => vec![Action::Revert(self.id.clone(), from, users)],
+            (State::Uninstalled, State::Disabled) => vec![
+                Action::Revert(self.id.clone(), State::Uninstalled, users),
+                Action::Disable(self.id.clone(), users),
+            ],
+            (_, State::Disabled) => vec![Action::Disable(self.id.clone(), users)],
+            (_, State::Uninstalled) => vec![Action::Uninstall(package.clone(), users)],
+        }
+    }
+}
+
+impl crate::App {
+    pub fn open_profile(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("zilch profile", &["ini"])
+            .pick_file()
+        else {
+            return;
+        };
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                self.notices
+                    .push(format!("failed to read profile {}: {e}", path.display()));
+                return;
+            }
+        };
+
+        let Some(session) = self.session() else {
+            return;
+        };
+        match Profile::parse(&text) {
+            Ok(profile) => {
+                self.pending_profile = Some(profile.diff(&session.info.serial, &session.entries))
+            }
+            Err(e) => self
+                .notices
+                .push(format!("failed to parse profile {}: {e}", path.display())),
+        }
+    }
+
+    pub fn profile_preview(&mut self, ctx: &egui::Context) {
+        let Some(plan) = self.pending_profile.as_ref() else {
+            return;
+        };
+        // the plan only makes sense for the device it was made for
+        if self.current_device.as_ref() != Some(&plan.serial) {
+            self.notices.push(format!(
+                "{} is no longer the current device, open the profile again",
+                plan.serial
+            ));
+            self.pending_profile = None;
+            return;
+        }
+
+        let mut apply = false;
+        let mut close = false;
+        let modal = Modal::new(Id::new("profile_preview")).show(ctx, |ui| {
+            ui.heading("Apply profile");
+            if plan.missing > 0 {
+                ui.label(format!(
+                    "{} packages in the profile are not present on this device.",
+                    plan.missing
+                ));
+            }
+
+            if plan.changes.is_empty() {
+                ui.label("The device already matches this profile.");
+            } else {
+                ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    for change in plan.changes.iter() {
+                        ui.label(
+                            RichText::new(format!(
+                                "{}: {} → {}",
+                                change.id,
+                                change.from.name(),
+                                change.to.name()
+                            ))
+                            .monospace()
+                            .size(10.0),
+                        );
+                    }
+                });
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(!plan.changes.is_empty(), egui::Button::new("apply"))
+                    .clicked()
+                {
+                    apply = true;
+                }
+                if ui.button("cancel").clicked() {
+                    close = true;
+                }
+            });
+        });
+        close |= modal.should_close();
+
+        if apply
+            && let Some(plan) = self.pending_profile.take()
+            && let Some(session) = self.session()
+        {
+            let mut actions = vec![];
+            for change in plan.changes.iter() {
+                let Some(entry) = session.entries.get(&change.id) else {
+                    continue;
+                };
+                actions.extend(change.actions(&entry.package, session.user_scope));
+            }
+            self.queue_actions(actions);
+        } else if close {
+            self.pending_profile = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(states: &[(&str, State)]) -> BTreeMap<String, Entry> {
+        states
+            .iter()
+            .map(|&(id, state)| (id.to_string(), Entry::test(id, state)))
+            .collect()
+    }
+
+    #[test]
+    fn parses_what_it_saves() {
+        let entries = entries(&[
+            ("a", State::Enabled),
+            ("b", State::Disabled),
+            ("c", State::Uninstalled),
+        ]);
+        let text = Profile::from_entries(&entries).to_string();
+        assert_eq!(text, "disabled=b\nenabled=a\nuninstalled=c");
+
+        let profile = Profile::parse(&format!("\n{text}\nenabled= d , ,e\n")).unwrap();
+        let wanted: Vec<(&str, State)> = profile
+            .wanted_states()
+            .map(|(id, state)| (id.as_str(), state))
+            .collect();
+        assert_eq!(
+            wanted,
+            [
+                ("a", State::Enabled),
+                ("d", State::Enabled),
+                ("e", State::Enabled),
+                ("b", State::Disabled),
+                ("c", State::Uninstalled),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(matches!(
+            Profile::parse("enabled=a\ndisabled"),
+            Err(ProfileError::MissingSeparator(2))
+        ));
+        assert!(matches!(
+            Profile::parse("frozen=a"),
+            Err(ProfileError::UnknownKey(key)) if key == "frozen"
+        ));
+    }
+
+    #[test]
+    fn diffs_against_the_device() {
+        let device = entries(&[
+            ("a", State::Enabled),
+            ("b", State::Uninstalled),
+            ("c", State::Disabled),
+        ]);
+        let profile = Profile::parse("enabled=c\ndisabled=b,gone\nuninstalled=").unwrap();
+        let plan = profile.diff("FAKE0001", &device);
+
+        assert_eq!(plan.missing, 1);
+        let changes: Vec<(&str, State, State)> = plan
+            .changes
+            .iter()
+            .map(|change| (change.id.as_str(), change.from, change.to))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("c", State::Disabled, State::Enabled),
+                ("b", State::Uninstalled, State::Disabled),
+            ]
+        );
+
+        // an uninstalled package has to come back before it can be disabled
+        let actions = plan.changes[1].actions(&device["b"].package, UserScope::default());
+        assert!(matches!(
+            actions.as_slice(),
+            [
+                Action::Revert(_, State::Uninstalled, _),
+                Action::Disable(..)
+            ]
+        ));
+    }
+}
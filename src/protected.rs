@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::adb_shell_text::AndroidDevice;
+use crate::{PackageIdentifier, ShellRunError};
+
+/// Packages without which Android does not boot, cannot be operated or cannot be fixed over adb.
+const CRITICAL: [&str; 12] = [
+    "android",
+    "com.android.systemui",
+    "com.android.settings",
+    "com.android.phone",
+    "com.android.providers.settings",
+    "com.android.providers.telephony",
+    "com.android.server.telecom",
+    // runs every adb shell command, zilch included
+    "com.android.shell",
+    "com.android.packageinstaller",
+    "com.google.android.packageinstaller",
+    "com.android.permissioncontroller",
+    "com.google.android.permissioncontroller",
+];
+
+/// Why a package must not be uninstalled or disabled.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    Critical,
+    Launcher,
+    Keyboard,
+    Dialer,
+    Sms,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Role::Critical => "needed by the system",
+            Role::Launcher => "the default launcher",
+            Role::Keyboard => "the default keyboard",
+            Role::Dialer => "the default dialer",
+            Role::Sms => "the default SMS app",
+        })
+    }
+}
+
+/// Commands printing the package filling each role, as a package name or a component.
+const ROLE_QUERIES: [(Role, &str); 4] = [
+    (
+        Role::Launcher,
+        "cmd package resolve-activity --brief -a android.intent.action.MAIN -c android.intent.category.HOME",
+    ),
+    (Role::Keyboard, "settings get secure default_input_method"),
+    (Role::Dialer, "telecom get-default-dialer"),
+    (Role::Sms, "settings get secure sms_default_application"),
+];
+
+/// The package in the last line of a role query, if it names one. Devices too old for a query
+/// print an error instead, and `settings` prints `null` for roles nobody fills.
+fn package_of(output: &str) -> Option<&str> {
+    let line = output
+        .lines()
+        .rev()
+        .map(str::trim)
+        .find(|l| !l.is_empty())?;
+    let id = line.split('/').next()?;
+    (id.contains('.') && !id.contains(char::is_whitespace)).then_some(id)
+}
+
+/// The critical packages along with whichever packages the device uses as its launcher,
+/// keyboard, dialer and SMS app right now.
+pub fn detect<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+) -> Result<BTreeMap<PackageIdentifier, Role>, ShellRunError> {
+    let mut protected: BTreeMap<PackageIdentifier, Role> = CRITICAL
+        .iter()
+        .map(|id| (id.to_string(), Role::Critical))
+        .collect();
+    for (role, query) in ROLE_QUERIES {
+        if let Some(id) = package_of(&device.shell_command_text(query)?) {
+            protected.entry(id.to_string()).or_insert(role);
+        }
+    }
+    Ok(protected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_device::FakeDevice;
+
+    #[test]
+    fn finds_packages_in_role_queries() {
+        assert_eq!(
+            package_of(
+                "priority=0 preferredOrder=0 match=0x108000 specificIndex=-1 isDefault=true\n\
+                 com.android.launcher3/.uioverrides.QuickstepLauncher\n"
+            ),
+            Some("com.android.launcher3")
+        );
+        assert_eq!(
+            package_of(
+                "com.google.android.inputmethod.latin/com.android.inputmethod.latin.LatinIME\n"
+            ),
+            Some("com.google.android.inputmethod.latin")
+        );
+        assert_eq!(package_of("null\n"), None);
+        assert_eq!(
+            package_of("/system/bin/sh: telecom: inaccessible or not found\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn detects_the_packages_filling_roles() {
+        let mut device = FakeDevice::new(34)
+            .with_role(Role::Launcher, "com.example.launcher")
+            .with_role(Role::Sms, "com.example.sms");
+        let protected = detect(&mut device).unwrap();
+
+        assert_eq!(protected["android"], Role::Critical);
+        assert_eq!(protected["com.example.launcher"], Role::Launcher);
+        assert_eq!(protected["com.example.sms"], Role::Sms);
+        assert!(!protected.values().any(|role| *role == Role::Keyboard));
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+
+use crate::categories;
+use crate::listview::{Entry, State};
+
+type Entries = BTreeMap<String, Entry>;
+
+/// Selects every entry from `anchor` to `to` as they appear in `visible`, both included.
+/// Without an anchor on screen only `to` is selected.
+pub fn select_range(entries: &mut Entries, visible: &[String], anchor: Option<&str>, to: &str) {
+    let Some(end) = visible.iter().position(|id| id == to) else {
+        return;
+    };
+    let start = anchor
+        .and_then(|anchor| visible.iter().position(|id| id == anchor))
+        .unwrap_or(end);
+    for id in &visible[start.min(end)..=start.max(end)] {
+        if let Some(entry) = entries.get_mut(id) {
+            entry.selected = true;
+        }
+    }
+}
+
+/// Selects exactly the visible entries, clearing the selection of hidden ones like `invert`.
+pub fn select_all(entries: &mut Entries, visible: &[String]) {
+    for (id, entry) in entries.iter_mut() {
+        entry.selected = visible.contains(id);
+    }
+}
+
+/// Flips the selection of the visible entries, and clears it on the hidden ones
+/// so actions never land on something that cannot be seen.
+pub fn invert(entries: &mut Entries, visible: &[String]) {
+    for (id, entry) in entries.iter_mut() {
+        entry.selected = !entry.selected && visible.contains(id);
+    }
+}
+
+/// Selects exactly the visible Recommended packages that are still enabled.
+pub fn select_recommended(entries: &mut Entries, visible: &[String]) {
+    for (id, entry) in entries.iter_mut() {
+        entry.selected = entry.removal() == categories::RECOMMENDED
+            && entry.state == State::Enabled
+            && visible.contains(id);
+    }
+}
+
+impl crate::App {
+    /// Clicking an entry toggles it, shift clicking selects everything since the last click.
+    pub fn click_entry(&mut self, id: String, shift: bool) {
+        let visible = self.visible_ids();
+        let anchor = self.selection_anchor.take();
+        let Some(session) = self.session_mut() else {
+            return;
+        };
+        if shift {
+            select_range(&mut session.entries, &visible, anchor.as_deref(), &id);
+        } else if let Some(entry) = session.entries.get_mut(&id) {
+            entry.selected ^= true;
+        }
+        self.focused = Some(id.clone());
+        self.selection_anchor = Some(id);
+    }
+
+    pub fn select_all_visible(&mut self) {
+        let visible = self.visible_ids();
+        if let Some(session) = self.session_mut() {
+            select_all(&mut session.entries, &visible);
+        }
+    }
+
+    pub fn invert_selection(&mut self) {
+        let visible = self.visible_ids();
+        if let Some(session) = self.session_mut() {
+            invert(&mut session.entries, &visible);
+        }
+    }
+
+    pub fn select_recommended(&mut self) {
+        // show what gets selected
+        self.categories |= categories::RECOMMENDED;
+        let visible = self.visible_ids();
+        if let Some(session) = self.session_mut() {
+            select_recommended(&mut session.entries, &visible);
+        }
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection_anchor = None;
+        if let Some(session) = self.session_mut() {
+            for entry in session.entries.values_mut() {
+                entry.selected = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Metadata;
+
+    static RECOMMENDED: Metadata = Metadata {
+        description: "",
+        removal: categories::RECOMMENDED,
+    };
+
+    fn entries(states: &[(&str, State, bool)]) -> Entries {
+        states
+            .iter()
+            .map(|&(id, state, recommended)| {
+                let entry = Entry {
+                    metadata: recommended.then_some(&RECOMMENDED),
+                    ..Entry::test(id, state)
+                };
+                (id.to_string(), entry)
+            })
+            .collect()
+    }
+
+    fn selected(entries: &Entries) -> Vec<&str> {
+        entries
+            .values()
+            .filter(|entry| entry.selected)
+            .map(|entry| entry.package.id.as_str())
+            .collect()
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn ranges_follow_the_visible_order() {
+        let mut entries = entries(&[
+            ("a", State::Enabled, false),
+            ("b", State::Enabled, false),
+            ("c", State::Enabled, false),
+            ("d", State::Enabled, false),
+        ]);
+        let visible = ids(&["d", "b", "a", "c"]);
+
+        select_range(&mut entries, &visible, Some("a"), "d");
+        assert_eq!(selected(&entries), ["a", "b", "d"]);
+
+        // an anchor filtered out since selects only the clicked entry
+        entries
+            .values_mut()
+            .for_each(|entry| entry.selected = false);
+        select_range(&mut entries, &visible[1..], Some("d"), "c");
+        assert_eq!(selected(&entries), ["c"]);
+    }
+
+    #[test]
+    fn select_all_and_invert_clear_hidden_entries() {
+        let mut entries = entries(&[
+            ("a", State::Enabled, false),
+            ("b", State::Enabled, false),
+            ("c", State::Enabled, false),
+        ]);
+        let visible = ids(&["a", "b"]);
+        entries.get_mut("c").unwrap().selected = true;
+
+        select_all(&mut entries, &visible);
+        assert_eq!(selected(&entries), ["a", "b"]);
+
+        entries.get_mut("a").unwrap().selected = false;
+        entries.get_mut("c").unwrap().selected = true;
+        invert(&mut entries, &visible);
+        assert_eq!(selected(&entries), ["a"]);
+    }
+
+    #[test]
+    fn selects_visible_recommended_packages_still_enabled() {
+        let mut entries = entries(&[
+            ("a", State::Enabled, true),
+            ("b", State::Disabled, true),
+            ("c", State::Enabled, false),
+            ("d", State::Uninstalled, true),
+            ("e", State::Enabled, true),
+        ]);
+        entries.get_mut("c").unwrap().selected = true;
+
+        // e is hidden by the search
+        select_recommended(&mut entries, &ids(&["a", "b", "c", "d"]));
+        assert_eq!(selected(&entries), ["a"]);
+    }
+}
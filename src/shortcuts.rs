@@ -1,43 +1,221 @@
+use egui::Key;
+
+use crate::action::Action;
+use crate::listview::{Entry, State};
+use crate::profile::Profile;
+use crate::users::UserScope;
+
+/// What the `u`, `d` and `r` keys do to the focused or selected packages.
+#[derive(Clone, Copy)]
+pub enum Command {
+    Uninstall,
+    Disable,
+    Revert,
+}
+
+/// Actions carrying out the command on those of `entries` it makes sense for.
+pub fn command_actions<'a>(
+    command: Command,
+    entries: impl Iterator<Item = &'a Entry>,
+    users: UserScope,
+) -> Vec<Action> {
+    entries
+        .filter_map(|entry| match (command, entry.state) {
+            (Command::Uninstall, State::Enabled | State::Disabled) => {
+                Some(Action::Uninstall(entry.package.clone(), users))
+            }
+            (Command::Disable, State::Enabled) => {
+                Some(Action::Disable(entry.package.id.clone(), users))
+            }
+            (Command::Revert, State::Uninstalled | State::Disabled) => {
+                Some(Action::Revert(entry.package.id.clone(), entry.state, users))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The entry `steps` away from the focused one, staying within the visible entries.
+/// Without a visible focus, moving down starts at the top and moving up at the bottom.
+pub fn move_focus(visible: &[String], focused: Option<&str>, steps: isize) -> Option<String> {
+    let last = visible.len().checked_sub(1)?;
+    let index = match focused.and_then(|focused| visible.iter().position(|id| id == focused)) {
+        Some(index) => index.saturating_add_signed(steps).min(last),
+        None if steps > 0 => 0,
+        None => last,
+    };
+    Some(visible[index].clone())
+}
+
 impl crate::App {
+    fn dialog_open(&self) -> bool {
+        self.pending_confirmation.is_some()
+            || self.pending_profile.is_some()
+            || self.history.is_some()
+            || self.dry_run_plan.is_some()
+            || self.connect_dialog.is_some()
+    }
+
+    fn navigate(&mut self, ui: &mut egui::Ui) {
+        let steps = ui.input(|i| {
+            if !i.modifiers.is_none() {
+                0
+            } else if i.key_pressed(Key::ArrowDown) || i.key_pressed(Key::J) {
+                1
+            } else if i.key_pressed(Key::ArrowUp) || i.key_pressed(Key::K) {
+                -1
+            } else if i.key_pressed(Key::PageDown) {
+                20
+            } else if i.key_pressed(Key::PageUp) {
+                -20
+            } else if i.key_pressed(Key::End) {
+                isize::MAX
+            } else if i.key_pressed(Key::Home) {
+                isize::MIN
+            } else {
+                0
+            }
+        });
+        if steps != 0 {
+            self.focused = move_focus(&self.visible_ids(), self.focused.as_deref(), steps);
+            self.scroll_to_focus = true;
+            ui.ctx().request_repaint();
+        }
+
+        self.run_command(ui);
+        let Some(focused) = self.focused.clone() else {
+            return;
+        };
+        if ui.input(|i| i.modifiers.is_none() && i.key_pressed(Key::Space))
+            && let Some(entry) = self
+                .session_mut()
+                .and_then(|session| session.entries.get_mut(&focused))
+        {
+            entry.selected ^= true;
+            self.selection_anchor = Some(focused.clone());
+        }
+        if ui.input(|i| i.modifiers.is_none() && i.key_pressed(Key::Enter)) {
+            self.expanded = match self.expanded {
+                Some(ref expanded) if *expanded == focused => None,
+                _ => Some(focused.clone()),
+            };
+        }
+    }
+
+    /// Runs the command typed on the selected packages, or on the focused one if none are.
+    fn run_command(&mut self, ui: &mut egui::Ui) {
+        let command = ui.input(|i| {
+            if !i.modifiers.is_none() {
+                None
+            } else if i.key_pressed(Key::U) {
+                Some(Command::Uninstall)
+            } else if i.key_pressed(Key::D) {
+                Some(Command::Disable)
+            } else if i.key_pressed(Key::R) {
+                Some(Command::Revert)
+            } else {
+                None
+            }
+        });
+        let Some(command) = command else {
+            return;
+        };
+        let Some(session) = self.session() else {
+            return;
+        };
+        let selected: Vec<&Entry> = session.entries.values().filter(|e| e.selected).collect();
+        let actions = if selected.is_empty() {
+            let focused = self.focused.as_ref().and_then(|id| session.entries.get(id));
+            command_actions(command, focused.into_iter(), session.user_scope)
+        } else {
+            command_actions(command, selected.into_iter(), session.user_scope)
+        };
+        self.confirm_actions(actions);
+    }
+
     pub fn handle_shortcuts(&mut self, ui: &mut egui::Ui, search_modal: egui::Response) {
         if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
-            for (_id, entry) in self.entries.iter_mut() {
-                entry.selected = false;
+            self.clear_selection();
+        }
+
+        // keys typed into a text field are left to it, and dialogs sit on top of the list
+        let list_has_keyboard = !ui.ctx().wants_keyboard_input() && !self.dialog_open();
+        if list_has_keyboard {
+            self.navigate(ui);
+            if ui.input(|i| i.modifiers.command && i.key_pressed(egui::Key::A)) {
+                self.select_all_visible();
+            }
+            if ui.input(|i| i.modifiers.command && i.key_pressed(egui::Key::I)) {
+                self.invert_selection();
+            }
+            if ui.input(|i| i.modifiers.command && i.key_pressed(egui::Key::R)) {
+                self.select_recommended();
             }
         }
         if ui.input(|i| {
-            i.key_pressed(egui::Key::S)
-                || i.key_pressed(egui::Key::Slash)
+            (list_has_keyboard && (i.key_pressed(egui::Key::S) || i.key_pressed(egui::Key::Slash)))
                 || (i.modifiers.ctrl && i.key_pressed(egui::Key::F))
         }) {
             search_modal.request_focus();
         }
 
         if ui.input(|i| i.key_pressed(egui::Key::S) && i.modifiers.ctrl)
+            && let Some(session) = self.session()
             && let Some(path) = rfd::FileDialog::new()
                 .set_file_name("zilch.ini")
                 .save_file()
         {
-            let mut enabled = vec![];
-            let mut uninstalled = vec![];
-            let mut disabled = vec![];
-            for (id, entry) in self.entries.iter() {
-                match entry.state {
-                    crate::listview::State::Enabled => enabled.push(id.clone()),
-                    crate::listview::State::Disabled => disabled.push(id.clone()),
-                    crate::listview::State::Uninstalled => uninstalled.push(id.clone()),
-                }
-            }
-
-            let contents = format!(
-                "disabled={}\nenabled={}\nuninstalled={}",
-                disabled.join(","),
-                enabled.join(","),
-                uninstalled.join(",")
-            );
+            let contents = Profile::from_entries(&session.entries).to_string();
             if let Err(e) = std::fs::write(&path, contents) {
                 eprintln!("failed to write device state to {}: {e}", path.display());
             };
         }
+
+        if ui.input(|i| i.key_pressed(egui::Key::O) && i.modifiers.ctrl) {
+            self.open_profile();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str, state: State) -> Entry {
+        Entry::test(id, state)
+    }
+
+    #[test]
+    fn focus_moves_within_visible_entries() {
+        let visible: Vec<String> = ["a", "b", "c"].map(String::from).into();
+        assert_eq!(move_focus(&visible, None, 1).as_deref(), Some("a"));
+        assert_eq!(move_focus(&visible, None, -1).as_deref(), Some("c"));
+        assert_eq!(move_focus(&visible, Some("a"), 1).as_deref(), Some("b"));
+        assert_eq!(move_focus(&visible, Some("a"), -1).as_deref(), Some("a"));
+        assert_eq!(move_focus(&visible, Some("b"), 20).as_deref(), Some("c"));
+        assert_eq!(
+            move_focus(&visible, Some("c"), isize::MIN).as_deref(),
+            Some("a")
+        );
+        assert_eq!(move_focus(&visible, Some("gone"), 1).as_deref(), Some("a"));
+        assert_eq!(move_focus(&[], Some("a"), 1), None);
+    }
+
+    #[test]
+    fn commands_skip_packages_they_do_not_apply_to() {
+        let entries = [
+            entry("enabled", State::Enabled),
+            entry("disabled", State::Disabled),
+            entry("uninstalled", State::Uninstalled),
+        ];
+        let ids = |command| {
+            command_actions(command, entries.iter(), UserScope::default())
+                .iter()
+                .map(|action| action.package_id().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(Command::Uninstall), ["enabled", "disabled"]);
+        assert_eq!(ids(Command::Disable), ["enabled"]);
+        assert_eq!(ids(Command::Revert), ["disabled", "uninstalled"]);
     }
 }
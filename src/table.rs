@@ -0,0 +1,339 @@
+use std::cmp::Ordering;
+
+use egui::{Align, Image, Layout, RichText, Sense, SidePanel, Vec2, vec2};
+use egui_extras::{Column, TableBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::categories;
+use crate::icons;
+use crate::listview::{Entry, State, format_size};
+
+/// Where the sort order is kept between sessions. Column widths are kept by egui itself.
+pub const SORT_KEY: &str = "package_table_sort";
+const ICON_SIZE: Vec2 = vec2(18.0, 18.0);
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortColumn {
+    Label,
+    Id,
+    Category,
+    State,
+    Size,
+}
+
+const COLUMNS: [(SortColumn, &str); 5] = [
+    (SortColumn::Label, "Label"),
+    (SortColumn::Id, "Package"),
+    (SortColumn::Category, "Category"),
+    (SortColumn::State, "State"),
+    (SortColumn::Size, "Size"),
+];
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Sort {
+    pub column: SortColumn,
+    pub ascending: bool,
+}
+
+impl Default for Sort {
+    fn default() -> Self {
+        Sort {
+            column: SortColumn::Id,
+            ascending: true,
+        }
+    }
+}
+
+impl Sort {
+    fn compare(&self, a: &Entry, b: &Entry) -> Ordering {
+        // categories go from Recommended to Unidentified, like the filter buttons
+        let category = |entry: &Entry| {
+            categories::VALUES
+                .iter()
+                .position(|&value| value == entry.removal())
+        };
+        let ordering = match self.column {
+            SortColumn::Label => a
+                .package
+                .label
+                .to_lowercase()
+                .cmp(&b.package.label.to_lowercase()),
+            SortColumn::Id => Ordering::Equal,
+            SortColumn::Category => category(a).cmp(&category(b)),
+            SortColumn::State => a.state.name().cmp(b.state.name()),
+            SortColumn::Size => a.package.size.cmp(&b.package.size),
+        }
+        .then_with(|| a.package.id.cmp(&b.package.id));
+
+        if self.ascending {
+            ordering
+        } else {
+            ordering.reverse()
+        }
+    }
+
+    /// Sorts by the column, or flips the direction if it already sorts by it.
+    fn toggle(&mut self, column: SortColumn) {
+        if self.column == column {
+            self.ascending ^= true;
+        } else {
+            *self = Sort {
+                column,
+                ascending: true,
+            };
+        }
+    }
+}
+
+impl crate::App {
+    /// IDs of the entries passing the search and category filter, in table order.
+    pub fn visible_ids(&self) -> Vec<String> {
+        let Some(session) = self.session() else {
+            return vec![];
+        };
+        let query_lower = self.search_query.to_lowercase();
+        let mut visible: Vec<&Entry> = session
+            .entries
+            .values()
+            .filter(|entry| {
+                let entry_removal = entry.removal();
+                (entry.package.id.to_lowercase().contains(&query_lower)
+                    || entry.package.label.to_lowercase().contains(&query_lower)
+                    || entry
+                        .tags()
+                        .iter()
+                        .any(|tag| tag.to_lowercase().contains(&query_lower)))
+                    && (entry_removal & self.categories == entry_removal)
+            })
+            .collect();
+        visible.sort_by(|a, b| self.sort.compare(a, b));
+        visible
+            .into_iter()
+            .map(|entry| entry.package.id.clone())
+            .collect()
+    }
+
+    pub fn package_table(&mut self, ui: &mut egui::Ui) {
+        let visible = self.visible_ids();
+        let Some(session) = self
+            .current_device
+            .as_ref()
+            .and_then(|serial| self.sessions.get_mut(serial))
+        else {
+            return;
+        };
+
+        // labels would otherwise swallow the clicks meant for their row
+        ui.style_mut().interaction.selectable_labels = false;
+
+        let mut sort = self.sort;
+        let mut expand = None;
+        let mut click = None;
+        let mut table = TableBuilder::new(ui)
+            .id_salt("package_table")
+            .striped(true)
+            .resizable(true)
+            .sense(Sense::click())
+            .cell_layout(Layout::left_to_right(Align::Center))
+            .column(Column::initial(180.0).at_least(60.0).clip(true))
+            .column(Column::initial(260.0).at_least(80.0).clip(true))
+            .column(Column::initial(100.0).at_least(60.0).clip(true))
+            .column(Column::initial(90.0).at_least(60.0).clip(true))
+            .column(Column::remainder().at_least(60.0));
+        if self.scroll_to_focus {
+            self.scroll_to_focus = false;
+            let focused = visible
+                .iter()
+                .position(|id| Some(id) == self.focused.as_ref());
+            if let Some(index) = focused {
+                table = table.scroll_to_row(index, None);
+            }
+        }
+        let focused = self.focused.as_deref();
+        table
+            .header(24.0, |mut header| {
+                for (column, name) in COLUMNS {
+                    header.col(|ui| {
+                        let arrow = match (sort.column == column, sort.ascending) {
+                            (false, _) => "",
+                            (true, true) => " ▲",
+                            (true, false) => " ▼",
+                        };
+                        if ui
+                            .selectable_label(sort.column == column, format!("{name}{arrow}"))
+                            .clicked()
+                        {
+                            sort.toggle(column);
+                        }
+                    });
+                }
+            })
+            .body(|body| {
+                body.rows(24.0, visible.len(), |mut row| {
+                    let Some(entry) = session.entries.get_mut(&visible[row.index()]) else {
+                        return;
+                    };
+                    row.set_selected(entry.selected);
+                    // the keyboard focus looks like the mouse hovering the row
+                    if focused == Some(entry.package.id.as_str()) {
+                        row.set_hovered(true);
+                    }
+
+                    // strike through apps that are not enabled
+                    let text = |text: &str| {
+                        let text = RichText::new(text);
+                        if entry.state == State::Enabled {
+                            text
+                        } else {
+                            text.strikethrough().weak()
+                        }
+                    };
+                    row.col(|ui| {
+                        // keeps labels lined up whether or not their app has an icon
+                        let (rect, _) = ui.allocate_exact_size(ICON_SIZE, Sense::hover());
+                        if let Some(icon) = session.icons.get(&entry.package.id) {
+                            Image::from_bytes(
+                                icons::uri(
+                                    &session.info.serial,
+                                    &entry.package.id,
+                                    entry.package.version,
+                                ),
+                                icon.clone(),
+                            )
+                            .paint_at(ui, rect);
+                        }
+                        ui.label(text(&entry.package.label).size(12.0));
+                    });
+                    row.col(|ui| {
+                        ui.label(text(&entry.package.id).monospace().size(10.0));
+                    });
+                    row.col(|ui| {
+                        ui.label(categories::value_to_name(entry.removal()));
+                    });
+                    row.col(|ui| {
+                        ui.label(entry.state.name());
+                    });
+                    row.col(|ui| {
+                        ui.label(entry.package.size.map(format_size).unwrap_or_default());
+                    });
+
+                    let response = row.response();
+                    if response.double_clicked() {
+                        // the first click of a double click already toggled it
+                        expand = Some(entry.package.id.clone());
+                        entry.selected ^= true;
+                    } else if response.clicked() {
+                        let shift = response.ctx.input(|i| i.modifiers.shift);
+                        click = Some((entry.package.id.clone(), shift));
+                    }
+                });
+            });
+
+        self.sort = sort;
+        if let Some(id) = expand {
+            self.expanded = Some(id);
+        }
+        if let Some((id, shift)) = click {
+            self.click_entry(id, shift);
+        }
+    }
+
+    /// Describes the entry last double clicked, next to the table.
+    pub fn entry_panel(&mut self, ctx: &egui::Context) {
+        let Some(id) = self.expanded.clone() else {
+            return;
+        };
+        let Some(session) = self
+            .current_device
+            .as_ref()
+            .and_then(|serial| self.sessions.get_mut(serial))
+        else {
+            return;
+        };
+        let Some(entry) = session.entries.get_mut(&id) else {
+            self.expanded = None;
+            return;
+        };
+
+        let mut close = false;
+        SidePanel::right("entry_panel")
+            .resizable(true)
+            .default_width(280.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if let Some(icon) = session.icons.get(&id) {
+                        ui.add(
+                            Image::from_bytes(
+                                icons::uri(&session.info.serial, &id, entry.package.version),
+                                icon.clone(),
+                            )
+                            .fit_to_exact_size(ICON_SIZE * 2.0),
+                        );
+                    }
+                    ui.heading(&entry.package.label);
+                    ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                        if ui.small_button("close").clicked() {
+                            close = true;
+                        }
+                    });
+                });
+                ui.label(RichText::new(&entry.package.id).monospace().size(10.0));
+                if let Some(role) = session.protected.get(&id) {
+                    ui.label(
+                        RichText::new(format!("Protected: {role}"))
+                            .color(ui.style().visuals.error_fg_color)
+                            .size(12.0),
+                    );
+                }
+                ui.separator();
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    entry.render_body(ui, &session.details_tx);
+                });
+            });
+
+        if close {
+            self.expanded = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str, label: &str, removal_state: State, size: Option<u64>) -> Entry {
+        let mut entry = Entry::test(id, removal_state);
+        entry.package.label = label.to_string();
+        entry.package.size = size;
+        entry
+    }
+
+    #[test]
+    fn sorts_by_column_then_id() {
+        let entries = [
+            entry("c", "beta", State::Enabled, Some(30)),
+            entry("a", "Beta", State::Disabled, None),
+            entry("b", "alpha", State::Uninstalled, Some(10)),
+        ];
+        let order = |sort: Sort| {
+            let mut sorted: Vec<&Entry> = entries.iter().collect();
+            sorted.sort_by(|a, b| sort.compare(a, b));
+            sorted
+                .into_iter()
+                .map(|entry| entry.package.id.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        let mut sort = Sort::default();
+        assert_eq!(order(sort), ["a", "b", "c"]);
+        sort.toggle(SortColumn::Label);
+        assert_eq!(order(sort), ["b", "a", "c"]);
+        sort.toggle(SortColumn::Label);
+        assert_eq!(order(sort), ["c", "a", "b"]);
+        sort.toggle(SortColumn::Size);
+        // packages of unknown size come first
+        assert_eq!(order(sort), ["a", "b", "c"]);
+        sort.toggle(SortColumn::State);
+        assert_eq!(order(sort), ["a", "c", "b"]);
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ShellRunError;
+use crate::adb_shell_text::AndroidDevice;
+
+/// An Android user, such as the owner, a secondary user or a work profile.
+#[derive(Clone)]
+pub struct AndroidUser {
+    pub id: u32,
+    pub name: String,
+}
+
+/// Which users package listing and actions apply to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserScope {
+    User(u32),
+    All,
+}
+
+impl Default for UserScope {
+    fn default() -> Self {
+        UserScope::User(0)
+    }
+}
+
+impl Display for UserScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserScope::User(id) => write!(f, "user {id}"),
+            UserScope::All => f.write_str("all users"),
+        }
+    }
+}
+
+impl UserScope {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "all" => Some(UserScope::All),
+            id => id.parse().ok().map(UserScope::User),
+        }
+    }
+
+    /// The concrete user IDs this scope covers on a device with the given users.
+    pub fn ids(self, users: &[AndroidUser]) -> Vec<u32> {
+        match self {
+            UserScope::User(id) => vec![id],
+            UserScope::All => users.iter().map(|user| user.id).collect(),
+        }
+    }
+}
+
+/// Reads the users on the device from `pm list users`, whose lines look like
+/// `UserInfo{10:Work profile:1030} running`.
+pub fn list_users<D: AndroidDevice + ?Sized>(
+    device: &mut D,
+) -> Result<Vec<AndroidUser>, ShellRunError> {
+    let raw_users_text = device.shell_command_text("pm list users")?;
+    let users: Vec<AndroidUser> = raw_users_text
+        .lines()
+        .filter_map(|line| {
+            let info = line.trim().strip_prefix("UserInfo{")?;
+            let (info, _) = info.split_once('}')?;
+            let mut fields = info.splitn(3, ':');
+            let id = fields.next()?.parse().ok()?;
+            let name = fields.next().unwrap_or_default().to_string();
+            Some(AndroidUser { id, name })
+        })
+        .collect();
+
+    if users.is_empty() {
+        // devices without multi-user support only have the owner
+        return Ok(vec![AndroidUser {
+            id: 0,
+            name: "Owner".to_string(),
+        }]);
+    }
+    Ok(users)
+}